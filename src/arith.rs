@@ -0,0 +1,72 @@
+use super::*;
+
+/// Apply binary op after promoting mixed operands, Int -> Float -> Double
+pub fn promote<F>(op: F, left: Word, right: Word) -> Result<Word, Error>
+where
+    F: Fn(Word, Word) -> Result<Word, Error>,
+{
+    match (left, right) {
+        (Word::Int(_), Word::Int(_)) => op(left, right),
+        (Word::Float(_), Word::Float(_)) => op(left, right),
+        (Word::Double(_), Word::Double(_)) => op(left, right),
+        (Word::Int(a), Word::Float(_)) => op(Word::Float(a as f32), right),
+        (Word::Float(_), Word::Int(b)) => op(left, Word::Float(b as f32)),
+        (Word::Float(a), Word::Double(_)) => op(Word::Double(a as f64), right),
+        (Word::Double(_), Word::Float(b)) => op(left, Word::Double(b as f64)),
+        _ => Err(Error::IllegalInst),
+    }
+}
+
+pub fn add(a: Word, b: Word) -> Result<Word, Error> {
+    match (a, b) {
+        (Word::Int(a), Word::Int(b)) => a.checked_add(b).map(Word::Int).ok_or(Error::IntegerOverflow),
+        (Word::Float(a), Word::Float(b)) => Ok(Word::Float(a + b)),
+        (Word::Double(a), Word::Double(b)) => Ok(Word::Double(a + b)),
+        _ => Err(Error::IllegalInst),
+    }
+}
+
+pub fn sub(a: Word, b: Word) -> Result<Word, Error> {
+    match (a, b) {
+        (Word::Int(a), Word::Int(b)) => a.checked_sub(b).map(Word::Int).ok_or(Error::IntegerOverflow),
+        (Word::Float(a), Word::Float(b)) => Ok(Word::Float(a - b)),
+        (Word::Double(a), Word::Double(b)) => Ok(Word::Double(a - b)),
+        _ => Err(Error::IllegalInst),
+    }
+}
+
+pub fn mul(a: Word, b: Word) -> Result<Word, Error> {
+    match (a, b) {
+        (Word::Int(a), Word::Int(b)) => a.checked_mul(b).map(Word::Int).ok_or(Error::IntegerOverflow),
+        (Word::Float(a), Word::Float(b)) => Ok(Word::Float(a * b)),
+        (Word::Double(a), Word::Double(b)) => Ok(Word::Double(a * b)),
+        _ => Err(Error::IllegalInst),
+    }
+}
+
+pub fn div(a: Word, b: Word) -> Result<Word, Error> {
+    match (a, b) {
+        (Word::Int(a), Word::Int(b)) => {
+            if b == 0 {
+                Err(Error::DivByZero)
+            } else {
+                a.checked_div(b).map(Word::Int).ok_or(Error::IntegerOverflow)
+            }
+        }
+        (Word::Float(a), Word::Float(b)) => {
+            if b == 0.0 {
+                Err(Error::DivByZero)
+            } else {
+                Ok(Word::Float(a / b))
+            }
+        }
+        (Word::Double(a), Word::Double(b)) => {
+            if b == 0.0 {
+                Err(Error::DivByZero)
+            } else {
+                Ok(Word::Double(a / b))
+            }
+        }
+        _ => Err(Error::IllegalInst),
+    }
+}
@@ -0,0 +1,428 @@
+use super::*;
+use crate::bytecode::Module;
+use crate::debug_info::Function;
+use crate::source_map::SourceLoc;
+
+/// Problem in the assembly source, with where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub loc: SourceLoc,
+    pub message: String,
+}
+
+/// Instruction before labels are resolved
+struct Line {
+    loc: SourceLoc,
+    inst_type: InstType,
+    operands: Vec<(String, SourceLoc)>,
+}
+
+struct Assembler<'a> {
+    file: &'a str,
+    lines: Vec<Line>,
+    data: Vec<Vec<Word>>,
+    labels: Vec<(String, usize)>,
+    functions: Vec<Function>,
+    open: Vec<(String, usize, SourceLoc)>,
+}
+
+fn is_ident(token: &str) -> bool {
+    let mut chars = token.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Operand spellings that never refer to a label: none, registers and
+/// float names
+fn reserved(token: &str) -> bool {
+    token == "_"
+        || token.eq_ignore_ascii_case("none")
+        || token.strip_prefix('r').is_some_and(|index| index.parse::<usize>().is_ok())
+        || matches!(token, "inf" | "nan")
+}
+
+/// Hex digits without sign, as a wide int so negating i64::MIN fits
+fn hex(digits: &str) -> Option<i128> {
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    i128::from_str_radix(digits, 16).ok()
+}
+
+/// Instruction type by its name, case insensitive
+fn mnemonic(name: &str) -> Option<InstType> {
+    InstType::ALL.iter()
+        .copied()
+        .find(|inst_type| format!("{:?}", inst_type).eq_ignore_ascii_case(name))
+}
+
+/// Resolve backslash escapes of a quoted literal
+fn unescape(body: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        out.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            c @ ('\\' | '\'' | '"') => c,
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+impl Assembler<'_> {
+    fn loc(&self, line: usize, col: usize) -> SourceLoc {
+        SourceLoc { file: self.file.to_string(), line, col }
+    }
+
+    fn error<T>(&self, loc: SourceLoc, message: impl Into<String>) -> Result<T, AsmError> {
+        Err(AsmError { loc, message: message.into() })
+    }
+
+    /// Split a line into tokens with their columns: the mnemonic, then the
+    /// comma separated operands. Stops at a `;` comment
+    fn tokenize(&self, text: &str, line: usize, offset: usize) -> Result<Vec<(String, usize)>, AsmError> {
+        let mut tokens = Vec::new();
+        let mut current = String::new();
+        let mut start = 0;
+        let mut quote = None;
+        let mut escaped = false;
+
+        let flush = |current: &mut String, start: usize, tokens: &mut Vec<(String, usize)>| {
+            let token = current.trim();
+            if !token.is_empty() {
+                let indent = current.len() - current.trim_start().len();
+                tokens.push((token.to_string(), offset + start + indent + 1));
+            }
+            current.clear();
+        };
+
+        for (col, c) in text.char_indices() {
+            if let Some(q) = quote {
+                current.push(c);
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+                continue;
+            }
+
+            match c {
+                ';' => break,
+                '"' | '\'' => {
+                    if current.trim().is_empty() {
+                        start = col;
+                    }
+                    quote = Some(c);
+                    current.push(c);
+                }
+                ',' => {
+                    flush(&mut current, start, &mut tokens);
+                    start = col + 1;
+                }
+                // The mnemonic ends at the first whitespace
+                c if c.is_whitespace() && tokens.is_empty() && !current.trim().is_empty() => {
+                    flush(&mut current, start, &mut tokens);
+                    start = col + 1;
+                }
+                c => {
+                    if current.is_empty() {
+                        start = col;
+                    }
+                    current.push(c);
+                }
+            }
+        }
+
+        if quote.is_some() {
+            return self.error(self.loc(line, offset + start + 1), "unterminated literal");
+        }
+        flush(&mut current, start, &mut tokens);
+        Ok(tokens)
+    }
+
+    fn line(&mut self, text: &str, line: usize) -> Result<(), AsmError> {
+        // Label, possibly followed by an instruction
+        let mut offset = 0;
+        let trimmed = text.trim_start();
+        if let Some((name, _)) = trimmed.split_once(':').filter(|(name, _)| is_ident(name)) {
+            let indent = text.len() - trimmed.len();
+            self.define_label(name, self.loc(line, indent + 1))?;
+            offset = indent + name.len() + 1;
+        }
+
+        let mut tokens = self.tokenize(&text[offset..], line, offset)?.into_iter();
+        let Some((head, col)) = tokens.next() else {
+            return Ok(());
+        };
+
+        let loc = self.loc(line, col);
+        let operands: Vec<(String, SourceLoc)> = tokens
+            .map(|(token, col)| (token, self.loc(line, col)))
+            .collect();
+
+        match head.as_str() {
+            ".func" => {
+                let [(name, _)] = operands.as_slice() else {
+                    return self.error(loc, ".func takes a name");
+                };
+                self.define_label(name, loc.clone())?;
+                self.open.push((name.clone(), self.lines.len(), loc));
+                return Ok(());
+            }
+            ".end" => {
+                let Some((name, start, _)) = self.open.pop() else {
+                    return self.error(loc, ".end without .func");
+                };
+                self.functions.push(Function { name, start, end: self.lines.len() });
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let Some(inst_type) = mnemonic(&head) else {
+            return self.error(loc, format!("unknown instruction `{}`", head));
+        };
+        if operands.len() > 2 {
+            return self.error(operands[2].1.clone(), "too many operands");
+        }
+
+        self.lines.push(Line { loc, inst_type, operands });
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &str, loc: SourceLoc) -> Result<(), AsmError> {
+        if !is_ident(name) || mnemonic(name).is_some() || reserved(name) {
+            return self.error(loc, format!("invalid label `{}`", name));
+        }
+        if self.labels.iter().any(|(n, _)| n == name) {
+            return self.error(loc, format!("label `{}` defined twice", name));
+        }
+
+        self.labels.push((name.to_string(), self.lines.len()));
+        Ok(())
+    }
+
+    /// String literal as data segment, equal strings share one
+    fn data_segment(&mut self, string: &str) -> usize {
+        let segment: Vec<Word> = string.chars().map(Word::Char).collect();
+        match self.data.iter().position(|s| *s == segment) {
+            Some(index) => index,
+            None => {
+                self.data.push(segment);
+                self.data.len() - 1
+            }
+        }
+    }
+
+    fn operand(&mut self, inst_type: InstType, token: &str, loc: &SourceLoc) -> Result<Word, AsmError> {
+        let number = |token: &str| token.parse::<usize>().ok();
+
+        if token == "_" || token.eq_ignore_ascii_case("none") {
+            return Ok(Word::None);
+        }
+
+        if let Some(body) = token.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            let Some(string) = unescape(body) else {
+                return self.error(loc.clone(), "invalid escape");
+            };
+            return Ok(Word::Ptr(Pointer::Data(self.data_segment(&string))));
+        }
+
+        if let Some(body) = token.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+            let Some(string) = unescape(body) else {
+                return self.error(loc.clone(), "invalid escape");
+            };
+            let chars: Vec<char> = string.chars().collect();
+            return match chars.as_slice() {
+                [c] => Ok(Word::Char(*c)),
+                _ => self.error(loc.clone(), "char literal holds one char"),
+            };
+        }
+
+        if let Some(reg) = token.strip_prefix('r').and_then(number) {
+            return Ok(Word::Ptr(Pointer::Register(reg)));
+        }
+
+        if let Some((region, index)) = token.split_once(':') {
+            let ptr = match (region, number(index)) {
+                ("heap", Some(i)) => Pointer::Heap(i),
+                ("stack", Some(i)) => Pointer::Stack(i),
+                ("data", Some(i)) => Pointer::Data(i),
+                ("file", Some(i)) => Pointer::Files(i),
+                _ => return self.error(loc.clone(), format!("invalid pointer `{}`", token)),
+            };
+            return Ok(Word::Ptr(ptr));
+        }
+
+        if is_ident(token) && !reserved(token) {
+            return match self.labels.iter().find(|(name, _)| name == token) {
+                Some((_, index)) => Ok(Word::Int(*index as i64)),
+                None => self.error(loc.clone(), format!("unknown label `{}`", token)),
+            };
+        }
+
+        let word = match inst_type {
+            InstType::Pushf => token.parse().ok().map(Word::Float),
+            InstType::Pushd => token.parse().ok().map(Word::Double),
+            _ => {
+                let int = match token.strip_prefix("0x") {
+                    Some(digits) => hex(digits).and_then(|n| i64::try_from(n).ok()),
+                    None => match token.strip_prefix("-0x") {
+                        Some(digits) => hex(digits).and_then(|n| i64::try_from(-n).ok()),
+                        None => token.parse().ok(),
+                    },
+                };
+                int.map(Word::Int).or_else(|| token.parse().ok().map(Word::Double))
+            }
+        };
+
+        match word {
+            Some(word) => Ok(word),
+            None => self.error(loc.clone(), format!("invalid operand `{}`", token)),
+        }
+    }
+
+    fn finish(mut self) -> Result<Module, AsmError> {
+        if let Some((name, _, loc)) = self.open.pop() {
+            return self.error(loc, format!("missing .end for `{}`", name));
+        }
+
+        let mut debug_info = DebugInfo::new();
+        let mut program = Vec::with_capacity(self.lines.len());
+
+        for (index, line) in std::mem::take(&mut self.lines).into_iter().enumerate() {
+            let mut operand = [Word::None; 2];
+            for (slot, (token, loc)) in line.operands.iter().enumerate() {
+                operand[slot] = self.operand(line.inst_type, token, loc)?;
+            }
+
+            program.push(Inst::new(line.inst_type, operand));
+            debug_info.source_map.insert(index, line.loc);
+        }
+
+        self.functions.sort_by_key(|f| f.start);
+        debug_info.labels = self.labels;
+        debug_info.functions = self.functions;
+
+        Ok(Module { program, data: self.data, debug_info: Some(debug_info) })
+    }
+}
+
+/// Assemble source text, file is the name used in source positions.
+///
+/// One instruction per line, `name:` labels an instruction, `;` starts a
+/// comment and `.func name` ... `.end` marks a function. Operands are ints,
+/// floats, `'c'` chars, `"string"` literals (placed in data segments),
+/// registers `r0`, pointers like `heap:3`, `_` for none, and label names,
+/// which become the index they label
+pub fn assemble(source: &str, file: &str) -> Result<Module, AsmError> {
+    let mut assembler = Assembler {
+        file,
+        lines: Vec::new(),
+        data: Vec::new(),
+        labels: Vec::new(),
+        functions: Vec::new(),
+        open: Vec::new(),
+    };
+
+    for (number, text) in source.lines().enumerate() {
+        assembler.line(text, number + 1)?;
+    }
+
+    assembler.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = assemble(source, "test.asm").unwrap_err();
+        (err.loc.line, err.loc.col, err.message)
+    }
+
+    #[test]
+    fn labels_resolve_forward_and_backward() {
+        let module = assemble("start: jmp end\n  pushi 1\nend: jmp start\n", "test.asm").unwrap();
+        assert_eq!(module.program[0].operand[0], Word::Int(2));
+        assert_eq!(module.program[2].operand[0], Word::Int(0));
+
+        let labels = &module.debug_info.unwrap().labels;
+        assert_eq!(labels, &[("start".to_string(), 0), ("end".to_string(), 2)]);
+    }
+
+    #[test]
+    fn escapes() {
+        let module = assemble("  pushs \"a\\n\\\"b\\\\\"\n  pushc '\\t'\n  pushc ','\n", "test.asm").unwrap();
+        assert_eq!(module.data[0], "a\n\"b\\".chars().map(Word::Char).collect::<Vec<_>>());
+        assert_eq!(module.program[1].operand[0], Word::Char('\t'));
+        assert_eq!(module.program[2].operand[0], Word::Char(','));
+
+        assert_eq!(error("  pushs \"a\\q\"\n"), (1, 9, "invalid escape".to_string()));
+        assert_eq!(error("  pushs \"abc\n"), (1, 9, "unterminated literal".to_string()));
+        assert_eq!(error("  pushc '\\q'\n"), (1, 9, "invalid escape".to_string()));
+        assert_eq!(error("  pushc 'ab'\n"), (1, 9, "char literal holds one char".to_string()));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("  pushi 1\n  bogus 2\n"), (2, 3, "unknown instruction `bogus`".to_string()));
+        assert_eq!(error("  jmp nowhere\n"), (1, 7, "unknown label `nowhere`".to_string()));
+        assert_eq!(error("  mov r0, 1, 2\n"), (1, 14, "too many operands".to_string()));
+        assert_eq!(error("a: pushi 1\n  a: pushi 2\n"), (2, 3, "label `a` defined twice".to_string()));
+        assert_eq!(error(".func f\n  return\n"), (1, 1, "missing .end for `f`".to_string()));
+    }
+
+    #[test]
+    fn labels_cannot_shadow_operands() {
+        for name in ["r1", "none", "None", "_", "inf", "nan", "pushi"] {
+            let message = format!("invalid label `{}`", name);
+            assert_eq!(error(&format!("{}: pushi 1\n", name)), (1, 1, message));
+        }
+
+        assert!(assemble("r1x: jmp r1x\n", "test.asm").is_ok());
+    }
+
+    #[test]
+    fn int_limits() {
+        let module = assemble("  pushi -0x8000000000000000\n  pushi 0x7fffffffffffffff\n  pushi -0x10\n", "test.asm").unwrap();
+        assert_eq!(module.program[0].operand[0], Word::Int(i64::MIN));
+        assert_eq!(module.program[1].operand[0], Word::Int(i64::MAX));
+        assert_eq!(module.program[2].operand[0], Word::Int(-16));
+
+        assert_eq!(error("  pushi 0x8000000000000000\n").2, "invalid operand `0x8000000000000000`");
+        assert_eq!(error("  pushi -0x-5\n").2, "invalid operand `-0x-5`");
+    }
+
+    #[test]
+    fn module_round_trip() {
+        let source = ".func main\n  pushs \"hi\"\n  pushd 2.5\n  mov r1, heap:3\n  exit 0\n.end\n";
+        let module = assemble(source, "test.asm").unwrap();
+
+        let mut bytes = Vec::new();
+        module.save(&mut bytes).unwrap();
+        assert_eq!(Module::load(bytes.as_slice()), Ok(module.clone()));
+
+        bytes.push(0);
+        assert_eq!(Module::load(bytes.as_slice()), Err(Error::InvalidBytecode));
+
+        let module = Module { debug_info: None, ..module };
+        let mut bytes = Vec::new();
+        module.save(&mut bytes).unwrap();
+        assert_eq!(Module::load(bytes.as_slice()), Ok(module));
+        bytes.push(0);
+        assert_eq!(Module::load(bytes.as_slice()), Err(Error::InvalidBytecode));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::error::Error;
+
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Modes accepted by `Open`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenMode {
+    Read,
+    Truncate,
+    Append,
+    /// Write an existing file without truncating it, used to reopen files
+    /// of a restored snapshot
+    Write,
+}
+
+impl OpenMode {
+    pub fn from_i64(mode: i64) -> Option<Self> {
+        match mode {
+            0 => Some(OpenMode::Read),
+            1 => Some(OpenMode::Truncate),
+            2 => Some(OpenMode::Append),
+            _ => None,
+        }
+    }
+}
+
+/// Handle to an open file in `Machine::files`
+pub trait FileHandle: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> FileHandle for T {}
+
+/// Filesystem the file instructions go through
+pub trait FileSystem {
+    fn open(&mut self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn FileHandle>>;
+
+    /// Resolve symlinks, used by the sandbox. Filesystems without symlinks
+    /// only need to report missing paths
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(path.to_path_buf())
+    }
+
+    /// Whether path itself is a symlink, dangling or not
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+
+    fn exists(&mut self, path: &Path) -> bool {
+        self.open(path, OpenMode::Read).is_ok()
+    }
+
+    fn remove(&mut self, _path: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn rename(&mut self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn create_dir(&mut self, _path: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Names of the entries in a directory
+    fn read_dir(&mut self, _path: &Path) -> io::Result<Vec<String>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Default filesystem, the one of the host
+pub struct HostFs;
+
+impl FileSystem for HostFs {
+    fn open(&mut self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn FileHandle>> {
+        let file = match mode {
+            OpenMode::Read => OpenOptions::new().read(true).open(path)?,
+            OpenMode::Truncate => OpenOptions::new().write(true).create(true).truncate(true).open(path)?,
+            OpenMode::Append => OpenOptions::new().create(true).append(true).open(path)?,
+            OpenMode::Write => OpenOptions::new().write(true).open(path)?,
+        };
+
+        Ok(Box::new(file))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.is_symlink()
+    }
+
+    fn exists(&mut self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    /// A symlink is removed itself, not what it points to
+    fn remove(&mut self, path: &Path) -> io::Result<()> {
+        if std::fs::symlink_metadata(path)?.is_dir() {
+            std::fs::remove_dir(path)
+        } else {
+            std::fs::remove_file(path)
+        }
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir(path)
+    }
+
+    fn read_dir(&mut self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<String>>>()?;
+
+        names.sort();
+        Ok(names)
+    }
+}
+
+/// In-memory output that stays readable after being handed to the `Machine`,
+/// e.g. for capturing program output
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        SharedBuffer::default()
+    }
+
+    /// Bytes written so far
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    /// Bytes written so far, lossily decoded as UTF-8
+    pub fn contents_string(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Map host io errors onto VM errors
+pub fn io_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => Error::FileNotFound,
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+        _ => Error::IO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::Outcome;
+    use crate::Machine;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    /// Read-only filesystem of in-memory files
+    struct MemFs(HashMap<PathBuf, Vec<u8>>);
+
+    impl FileSystem for MemFs {
+        fn open(&mut self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn FileHandle>> {
+            match (mode, self.0.get(path)) {
+                (OpenMode::Read, Some(bytes)) => Ok(Box::new(Cursor::new(bytes.clone()))),
+                (OpenMode::Read, None) => Err(io::ErrorKind::NotFound.into()),
+                _ => Err(io::ErrorKind::PermissionDenied.into()),
+            }
+        }
+    }
+
+    fn load(source: &str) -> (Machine, SharedBuffer) {
+        let mut machine = Machine::from_module(crate::asm::assemble(source, "test.asm").unwrap());
+        let out = SharedBuffer::new();
+        machine.set_output(out.clone());
+        machine.set_input(Cursor::new(b"typed\n".to_vec()));
+        machine.set_fs(MemFs(HashMap::from([(PathBuf::from("notes.txt"), b"first\nsecond\n".to_vec())])));
+        (machine, out)
+    }
+
+    #[test]
+    fn program_uses_the_given_backends() {
+        let source = "read\nprintf \"in: %s\\n\"\npushi 0\nopen \"notes.txt\"\nreadf _\nprintf \"file: %s\\n\"\n";
+        let (mut machine, out) = load(source);
+
+        assert_eq!(machine.run(), Ok(Outcome::EndOfProgram));
+        assert_eq!(out.contents_string(), "in: typed\nfile: first\n");
+    }
+
+    #[test]
+    fn filesystem_errors_reach_the_program() {
+        let (mut machine, _) = load("pushi 0\nopen \"missing.txt\"\n");
+        assert_eq!(machine.run(), Err(Error::FileNotFound));
+
+        let (mut machine, _) = load("pushi 1\nopen \"notes.txt\"\n");
+        assert_eq!(machine.run(), Err(Error::PermissionDenied));
+    }
+}
@@ -0,0 +1,340 @@
+use super::*;
+use crate::backend::io_error;
+use crate::debug_info::Function;
+use crate::source_map::SourceLoc;
+use std::io::{Read, Write};
+
+/// Little endian encoder for words and instructions
+pub struct Encoder<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(out: W) -> Self {
+        Encoder { out }
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.out.write_all(bytes).map_err(io_error)
+    }
+
+    pub fn u8(&mut self, val: u8) -> Result<(), Error> {
+        self.bytes(&[val])
+    }
+
+    pub fn bool(&mut self, val: bool) -> Result<(), Error> {
+        self.u8(val as u8)
+    }
+
+    pub fn u64(&mut self, val: u64) -> Result<(), Error> {
+        self.bytes(&val.to_le_bytes())
+    }
+
+    pub fn usize(&mut self, val: usize) -> Result<(), Error> {
+        self.u64(val as u64)
+    }
+
+    pub fn str(&mut self, val: &str) -> Result<(), Error> {
+        self.usize(val.len())?;
+        self.bytes(val.as_bytes())
+    }
+
+    pub fn pointer(&mut self, ptr: Pointer) -> Result<(), Error> {
+        let tag = match ptr {
+            Pointer::Register(_) => 0,
+            Pointer::Stack(_) => 1,
+            Pointer::Heap(_) => 2,
+            Pointer::Files(_) => 3,
+            Pointer::Data(_) => 4,
+            Pointer::Native(_) => 5,
+        };
+        self.u8(tag)?;
+        self.usize(ptr.as_usize())
+    }
+
+    pub fn word(&mut self, word: Word) -> Result<(), Error> {
+        match word {
+            Word::Int(val) => {
+                self.u8(0)?;
+                self.bytes(&val.to_le_bytes())
+            }
+            Word::Float(val) => {
+                self.u8(1)?;
+                self.bytes(&val.to_bits().to_le_bytes())
+            }
+            Word::Double(val) => {
+                self.u8(2)?;
+                self.u64(val.to_bits())
+            }
+            Word::Ptr(ptr) => {
+                self.u8(3)?;
+                self.pointer(ptr)
+            }
+            Word::Char(c) => {
+                self.u8(4)?;
+                self.bytes(&(c as u32).to_le_bytes())
+            }
+            Word::Free => self.u8(5),
+            Word::None => self.u8(6),
+        }
+    }
+
+    /// Length prefixed words
+    pub fn words(&mut self, words: &[Word]) -> Result<(), Error> {
+        self.usize(words.len())?;
+        words.iter().try_for_each(|word| self.word(*word))
+    }
+
+    pub fn inst(&mut self, inst: &Inst) -> Result<(), Error> {
+        self.u8(inst.inst_type as u8)?;
+        self.word(inst.operand[0])?;
+        self.word(inst.operand[1])
+    }
+
+    /// Length prefixed instructions
+    pub fn program(&mut self, program: &[Inst]) -> Result<(), Error> {
+        self.usize(program.len())?;
+        program.iter().try_for_each(|inst| self.inst(inst))
+    }
+}
+
+/// Decoder for what Encoder wrote, malformed input is Error::InvalidBytecode
+pub struct Decoder<R: Read> {
+    input: R,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(input: R) -> Self {
+        Decoder { input }
+    }
+
+    pub fn bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut buf = [0; N];
+        self.input.read_exact(&mut buf).map_err(|_| Error::InvalidBytecode)?;
+        Ok(buf)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidBytecode),
+        }
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| Error::InvalidBytecode)
+    }
+
+    pub fn str(&mut self) -> Result<String, Error> {
+        let len = self.usize()?;
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len as u64).read_to_end(&mut bytes).map_err(|_| Error::InvalidBytecode)?;
+        if bytes.len() != len {
+            return Err(Error::InvalidBytecode);
+        }
+
+        String::from_utf8(bytes).map_err(|_| Error::InvalidBytecode)
+    }
+
+    /// Fails unless the input is used up
+    pub fn end(&mut self) -> Result<(), Error> {
+        match self.input.read(&mut [0]) {
+            Ok(0) => Ok(()),
+            _ => Err(Error::InvalidBytecode),
+        }
+    }
+
+    pub fn pointer(&mut self) -> Result<Pointer, Error> {
+        let tag = self.u8()?;
+        let val = self.usize()?;
+
+        Ok(match tag {
+            0 => Pointer::Register(val),
+            1 => Pointer::Stack(val),
+            2 => Pointer::Heap(val),
+            3 => Pointer::Files(val),
+            4 => Pointer::Data(val),
+            5 => Pointer::Native(val),
+            _ => return Err(Error::InvalidBytecode),
+        })
+    }
+
+    pub fn word(&mut self) -> Result<Word, Error> {
+        Ok(match self.u8()? {
+            0 => Word::Int(i64::from_le_bytes(self.bytes()?)),
+            1 => Word::Float(f32::from_bits(u32::from_le_bytes(self.bytes()?))),
+            2 => Word::Double(f64::from_bits(self.u64()?)),
+            3 => Word::Ptr(self.pointer()?),
+            4 => {
+                let c = u32::from_le_bytes(self.bytes()?);
+                Word::Char(char::from_u32(c).ok_or(Error::InvalidBytecode)?)
+            }
+            5 => Word::Free,
+            6 => Word::None,
+            _ => return Err(Error::InvalidBytecode),
+        })
+    }
+
+    /// Length prefixed words, grown as they are read so a corrupt length
+    /// cannot allocate more than the input holds
+    pub fn words(&mut self) -> Result<Vec<Word>, Error> {
+        let len = self.usize()?;
+        let mut words = Vec::new();
+        for _ in 0..len {
+            words.push(self.word()?);
+        }
+        Ok(words)
+    }
+
+    pub fn inst(&mut self) -> Result<Inst, Error> {
+        let inst_type = InstType::from_u8(self.u8()?).ok_or(Error::InvalidBytecode)?;
+        Ok(Inst::new(inst_type, [self.word()?, self.word()?]))
+    }
+
+    pub fn program(&mut self) -> Result<Vec<Inst>, Error> {
+        let len = self.usize()?;
+        let mut program = Vec::new();
+        for _ in 0..len {
+            program.push(self.inst()?);
+        }
+        Ok(program)
+    }
+}
+
+const MAGIC: &[u8; 8] = b"CVM46BC\0";
+const VERSION: u64 = 1;
+
+/// Assembled program: instructions, data segments and optional debug info
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    pub program: Vec<Inst>,
+    pub data: Vec<Vec<Word>>,
+    pub debug_info: Option<DebugInfo>,
+}
+
+impl Module {
+    pub fn save(&self, out: impl Write) -> Result<(), Error> {
+        let mut enc = Encoder::new(out);
+        enc.bytes(MAGIC)?;
+        enc.u64(VERSION)?;
+
+        enc.usize(self.data.len())?;
+        for segment in &self.data {
+            enc.words(segment)?;
+        }
+        enc.program(&self.program)?;
+
+        let Some(debug_info) = &self.debug_info else {
+            return enc.bool(false);
+        };
+        enc.bool(true)?;
+
+        // File names once, locations refer to them by index
+        let mut files: Vec<&str> = Vec::new();
+        for loc in debug_info.source_map.locs.iter().flatten() {
+            if !files.contains(&loc.file.as_str()) {
+                files.push(&loc.file);
+            }
+        }
+        enc.usize(files.len())?;
+        files.iter().try_for_each(|file| enc.str(file))?;
+
+        enc.usize(debug_info.source_map.locs.len())?;
+        for loc in &debug_info.source_map.locs {
+            enc.bool(loc.is_some())?;
+            if let Some(loc) = loc {
+                enc.usize(files.iter().position(|file| *file == loc.file).unwrap_or(0))?;
+                enc.usize(loc.line)?;
+                enc.usize(loc.col)?;
+            }
+        }
+
+        enc.usize(debug_info.labels.len())?;
+        for (name, index) in &debug_info.labels {
+            enc.str(name)?;
+            enc.usize(*index)?;
+        }
+
+        enc.usize(debug_info.functions.len())?;
+        for function in &debug_info.functions {
+            enc.str(&function.name)?;
+            enc.usize(function.start)?;
+            enc.usize(function.end)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(input: impl Read) -> Result<Module, Error> {
+        let mut dec = Decoder::new(input);
+        if &dec.bytes::<8>()? != MAGIC || dec.u64()? != VERSION {
+            return Err(Error::InvalidBytecode);
+        }
+
+        let mut data = Vec::new();
+        for _ in 0..dec.usize()? {
+            data.push(dec.words()?);
+        }
+        let program = dec.program()?;
+        let debug_info = match dec.bool()? {
+            true => Some(Self::load_debug_info(&mut dec)?),
+            false => None,
+        };
+        dec.end()?;
+
+        Ok(Module { program, data, debug_info })
+    }
+
+    fn load_debug_info(dec: &mut Decoder<impl Read>) -> Result<DebugInfo, Error> {
+        let mut files = Vec::new();
+        for _ in 0..dec.usize()? {
+            files.push(dec.str()?);
+        }
+
+        let mut debug_info = DebugInfo::new();
+        for _ in 0..dec.usize()? {
+            let loc = match dec.bool()? {
+                true => Some(SourceLoc {
+                    file: files.get(dec.usize()?).ok_or(Error::InvalidBytecode)?.clone(),
+                    line: dec.usize()?,
+                    col: dec.usize()?,
+                }),
+                false => None,
+            };
+            debug_info.source_map.locs.push(loc);
+        }
+
+        for _ in 0..dec.usize()? {
+            debug_info.labels.push((dec.str()?, dec.usize()?));
+        }
+
+        for _ in 0..dec.usize()? {
+            debug_info.functions.push(Function {
+                name: dec.str()?,
+                start: dec.usize()?,
+                end: dec.usize()?,
+            });
+        }
+
+        Ok(debug_info)
+    }
+}
+
+impl Machine {
+    /// Machine running an assembled or loaded module
+    pub fn from_module(module: Module) -> Self {
+        let mut machine = Machine::new(module.program);
+        machine.data = module.data;
+        machine.debug_info = module.debug_info;
+        machine
+    }
+}
@@ -0,0 +1,254 @@
+use super::*;
+use crate::source_map::SourceMap;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Which instructions executed and which way `Jeq`/`Jne` went
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    /// Executions per instruction index
+    pub hits: Vec<u64>,
+    /// Per instruction index: times a branch fell through and times it jumped
+    pub branches: Vec<[u64; 2]>,
+}
+
+fn is_branch(inst_type: InstType) -> bool {
+    matches!(inst_type, InstType::Jeq | InstType::Jne)
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    match total {
+        0 => 100.0,
+        _ => part as f64 * 100.0 / total as f64,
+    }
+}
+
+/// Hits, branch directions taken and possible of one source line
+#[derive(Default)]
+struct LineCoverage {
+    hits: u64,
+    /// (instruction index, fall through, jump) per branch on the line
+    branches: Vec<(usize, u64, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    fn record(&mut self, ip: usize, inst_type: InstType, next_ip: usize, executed: bool) {
+        if ip >= self.hits.len() {
+            self.hits.resize(ip + 1, 0);
+            self.branches.resize(ip + 1, [0; 2]);
+        }
+
+        self.hits[ip] += 1;
+        if executed && is_branch(inst_type) {
+            let jumped = next_ip != ip + 1;
+            self.branches[ip][jumped as usize] += 1;
+        }
+    }
+
+    pub fn hits(&self, index: usize) -> u64 {
+        self.hits.get(index).copied().unwrap_or(0)
+    }
+
+    /// Times the branch at index fell through and jumped
+    pub fn branch(&self, index: usize) -> [u64; 2] {
+        self.branches.get(index).copied().unwrap_or([0; 2])
+    }
+
+    /// Instructions executed and branch directions taken, with their totals
+    pub fn summary(&self, program: &[Inst]) -> (usize, usize, usize, usize) {
+        let executed = (0..program.len()).filter(|&i| self.hits(i) > 0).count();
+
+        let branches: Vec<usize> = (0..program.len())
+            .filter(|&i| is_branch(program[i].inst_type))
+            .collect();
+        let taken = branches.iter()
+            .map(|&i| self.branch(i).iter().filter(|&&n| n > 0).count())
+            .sum();
+
+        (executed, program.len(), taken, branches.len() * 2)
+    }
+
+    fn lines(&self, program: &[Inst], source_map: &SourceMap) -> BTreeMap<(String, usize), LineCoverage> {
+        let mut lines: BTreeMap<(String, usize), LineCoverage> = BTreeMap::new();
+
+        for (index, inst) in program.iter().enumerate() {
+            let Some(loc) = source_map.get(index) else {
+                continue;
+            };
+
+            let line = lines.entry((loc.file.clone(), loc.line)).or_default();
+            line.hits = line.hits.max(self.hits(index));
+            if is_branch(inst.inst_type) {
+                let [fall, jump] = self.branch(index);
+                line.branches.push((index, fall, jump));
+            }
+        }
+
+        lines
+    }
+
+    /// Human readable report, per source line if there is a source map,
+    /// otherwise per instruction
+    pub fn report(&self, program: &[Inst], source_map: Option<&SourceMap>) -> String {
+        let mut out = String::new();
+
+        let (executed, total, taken, directions) = self.summary(program);
+        let _ = writeln!(
+            out,
+            "instructions {}/{} ({:.1}%), branches {}/{} ({:.1}%)\n",
+            executed, total, percent(executed, total),
+            taken, directions, percent(taken, directions),
+        );
+
+        if let Some(source_map) = source_map {
+            for ((file, line), coverage) in self.lines(program, source_map) {
+                let _ = write!(out, "{:>8}  {}:{}", coverage.hits, file, line);
+                for (_, fall, jump) in coverage.branches {
+                    let _ = write!(out, "  fall through {}, jump {}", fall, jump);
+                }
+                out.push('\n');
+            }
+            return out;
+        }
+
+        for (index, inst) in program.iter().enumerate() {
+            let _ = write!(out, "{:>8}  {:>5}  {:?}", self.hits(index), index, inst.inst_type);
+            if is_branch(inst.inst_type) {
+                let [fall, jump] = self.branch(index);
+                let _ = write!(out, "  fall through {}, jump {}", fall, jump);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Report in the lcov tracefile format, for genhtml and editor plugins
+    pub fn lcov(&self, program: &[Inst], source_map: &SourceMap) -> String {
+        let mut files: BTreeMap<String, Vec<(usize, LineCoverage)>> = BTreeMap::new();
+        for ((file, line), coverage) in self.lines(program, source_map) {
+            files.entry(file).or_default().push((line, coverage));
+        }
+
+        let mut out = String::new();
+        for (file, lines) in files {
+            let _ = writeln!(out, "TN:\nSF:{}", file);
+
+            let (mut found, mut hit) = (0, 0);
+            for (line, coverage) in &lines {
+                for (index, fall, jump) in &coverage.branches {
+                    for (branch, taken) in [fall, jump].into_iter().enumerate() {
+                        let taken = match (coverage.hits, taken) {
+                            (0, _) => "-".to_string(),
+                            (_, taken) => taken.to_string(),
+                        };
+                        let _ = writeln!(out, "BRDA:{},{},{},{}", line, index, branch, taken);
+                    }
+                    found += 2;
+                    hit += (*fall > 0) as usize + (*jump > 0) as usize;
+                }
+            }
+            let _ = writeln!(out, "BRF:{}\nBRH:{}", found, hit);
+
+            for (line, coverage) in &lines {
+                let _ = writeln!(out, "DA:{},{}", line, coverage.hits);
+            }
+            let executed = lines.iter().filter(|(_, coverage)| coverage.hits > 0).count();
+            let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", lines.len(), executed);
+        }
+
+        out
+    }
+}
+
+impl Machine {
+    /// Record coverage of the instructions executed from now on
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Stop recording coverage and return it
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Coverage report of the loaded program, mapped through its source map
+    pub fn coverage_report(&self) -> Option<String> {
+        let coverage = self.coverage.as_ref()?;
+        let source_map = self.source_map().filter(|map| !map.locs.is_empty());
+        Some(coverage.report(&self.program, source_map))
+    }
+
+    /// Count an instruction that was just executed, ip is already past it
+    pub fn cover(&mut self, ip: usize, inst_type: InstType, executed: bool) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(ip, inst_type, self.ip, executed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loops twice, the last instruction is never reached
+    const SOURCE: &str = "mov r0, 2\nloop: pushr r0\npushi -1\nplus\npopr r0\npushr r0\njeq loop\nhalt\npushi 9\n";
+
+    fn covered() -> Machine {
+        let mut machine = Machine::from_module(crate::asm::assemble(SOURCE, "test.asm").unwrap());
+        machine.enable_coverage();
+        machine.run().unwrap();
+        machine
+    }
+
+    #[test]
+    fn hits_and_branch_directions() {
+        let machine = covered();
+        let coverage = machine.coverage().unwrap();
+
+        assert_eq!(coverage.hits, [1, 2, 2, 2, 2, 2, 2, 1]);
+        assert_eq!(coverage.hits(8), 0);
+        assert_eq!(coverage.branch(6), [1, 1]);
+        assert_eq!(coverage.branch(3), [0, 0]);
+        assert_eq!(coverage.summary(&machine.program), (8, 9, 2, 2));
+    }
+
+    #[test]
+    fn report_per_source_line() {
+        let report = covered().coverage_report().unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(lines[0], "instructions 8/9 (88.9%), branches 2/2 (100.0%)");
+        assert_eq!(lines[8], "       2  test.asm:7  fall through 1, jump 1");
+        assert_eq!(lines[10], "       0  test.asm:9");
+    }
+
+    #[test]
+    fn report_per_instruction_without_source_map() {
+        let mut machine = covered();
+        let coverage = machine.take_coverage().unwrap();
+        let report = coverage.report(&machine.program, None);
+
+        assert!(report.contains("\n       2      6  Jeq  fall through 1, jump 1\n"), "{}", report);
+        assert!(report.ends_with("       0      8  Pushi\n"), "{}", report);
+        assert!(machine.coverage_report().is_none());
+    }
+
+    #[test]
+    fn lcov_tracefile() {
+        let machine = covered();
+        let coverage = machine.coverage().unwrap();
+        let lcov = coverage.lcov(&machine.program, machine.source_map().unwrap());
+
+        assert!(lcov.starts_with("TN:\nSF:test.asm\nBRDA:7,6,0,1\nBRDA:7,6,1,1\nBRF:2\nBRH:2\nDA:1,1\n"), "{}", lcov);
+        assert!(lcov.ends_with("DA:9,0\nLF:9\nLH:8\nend_of_record\n"), "{}", lcov);
+    }
+}
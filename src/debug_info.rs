@@ -0,0 +1,96 @@
+use super::*;
+use crate::source_map::{SourceLoc, SourceMap};
+
+/// Function boundaries, instructions start..end
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What the assembler knows about a program that bytecode does not
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    pub source_map: SourceMap,
+    /// Label names with the instruction index they point at
+    pub labels: Vec<(String, usize)>,
+    pub functions: Vec<Function>,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        DebugInfo::default()
+    }
+
+    /// Innermost function containing the instruction
+    pub fn function_at(&self, index: usize) -> Option<&Function> {
+        self.functions.iter()
+            .filter(|f| f.start <= index && index < f.end)
+            .min_by_key(|f| f.end - f.start)
+    }
+
+    /// Name of the label at index, if any
+    pub fn label_at(&self, index: usize) -> Option<&str> {
+        self.labels.iter()
+            .find(|(_, at)| *at == index)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Index of a label
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.labels.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, at)| *at)
+    }
+
+    /// `file:line:col in function` for an instruction, or its index
+    pub fn describe(&self, index: usize) -> String {
+        let mut place = match self.source_map.get(index) {
+            Some(loc) => format!("{}:{}:{}", loc.file, loc.line, loc.col),
+            None => format!("instruction {}", index),
+        };
+
+        if let Some(function) = self.function_at(index) {
+            place.push_str(&format!(" in {}", function.name));
+        }
+        place
+    }
+}
+
+impl Machine {
+    pub fn set_debug_info(&mut self, debug_info: Option<DebugInfo>) {
+        self.debug_info = debug_info;
+    }
+
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    /// Source position of an instruction
+    pub fn location(&self, index: usize) -> Option<&SourceLoc> {
+        self.debug_info.as_ref()?.source_map.get(index)
+    }
+
+    /// Where an instruction is, in source terms if there is debug info
+    pub fn describe_location(&self, index: usize) -> String {
+        match &self.debug_info {
+            Some(debug_info) => debug_info.describe(index),
+            None => format!("instruction {}", index),
+        }
+    }
+
+    /// Index of the instruction that returned the last error
+    pub fn fault(&self) -> Option<usize> {
+        self.fault
+    }
+
+    /// Error with the place it was raised at, e.g.
+    /// `SegmentationFault at main.asm:12:5 in copy`
+    pub fn describe_error(&self, err: Error) -> String {
+        match self.fault {
+            Some(index) => format!("{:?} at {}", err, self.describe_location(index)),
+            None => format!("{:?}", err),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use super::*;
+use crate::run::Outcome;
+use crate::watch::{Access, WatchAction, WatchHit};
+use std::fmt::Write as _;
+use std::io::BufRead;
+
+/// Instructions the debugger can step back
+pub const HISTORY_LIMIT: usize = 100_000;
+
+/// Line oriented debugger driving a machine, see help() for the commands
+pub struct Debugger {
+    pub machine: Machine,
+    started: bool,
+    last: String,
+}
+
+/// Register `r0`, heap address `heap:3` or stack slot `stack:2`
+pub fn parse_location(text: &str) -> Option<Pointer> {
+    if let Some(index) = text.strip_prefix('r') {
+        return index.parse().ok().map(Pointer::Register);
+    }
+
+    let (region, index) = text.split_once(':')?;
+    let index = index.parse().ok()?;
+    match region {
+        "heap" => Some(Pointer::Heap(index)),
+        "stack" => Some(Pointer::Stack(index)),
+        _ => None,
+    }
+}
+
+fn location_name(ptr: Pointer) -> String {
+    match ptr {
+        Pointer::Register(index) => format!("r{}", index),
+        Pointer::Heap(index) => format!("heap:{}", index),
+        Pointer::Stack(index) => format!("stack:{}", index),
+        _ => format!("{:?}", ptr),
+    }
+}
+
+fn word_name(word: Option<Word>) -> String {
+    match word {
+        Some(word) => format!("{:?}", word),
+        None => "-".to_string(),
+    }
+}
+
+const HELP: &str = "\
+run, continue (c)         run until the program stops
+step (s) [n]              execute n instructions
+back (bs) [n]             undo the last n instructions, back to the
+                          last one that reached the host at most
+reverse-continue (rc)     step back to the previous write of a watched
+                          location or to a breakpoint
+break (b) <index|label>   stop before an instruction
+delete <index|label>      remove a breakpoint
+watch <loc> [read|write|rw] [log]
+                          pause (or only log) when loc is accessed,
+                          loc is r0, heap:3 or stack:2
+unwatch <loc>             remove a watchpoint
+log                       print and clear the watch log
+print (p) <loc>           print a register, heap address or stack slot
+regs, stack               print registers or the stack
+where                     print the next instruction and its source
+quit (q)                  leave the debugger
+";
+
+impl Debugger {
+    /// Debugger recording the last HISTORY_LIMIT instructions
+    pub fn new(mut machine: Machine) -> Self {
+        machine.record_history(HISTORY_LIMIT);
+        Debugger { machine, started: false, last: String::new() }
+    }
+
+    pub fn help() -> &'static str {
+        HELP
+    }
+
+    /// Execute one command and return what it prints. An empty line repeats
+    /// the last command
+    pub fn command(&mut self, line: &str) -> String {
+        let line = match line.trim() {
+            "" => self.last.clone(),
+            line => line.to_string(),
+        };
+        self.last = line.clone();
+
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return String::new();
+        };
+        let args: Vec<&str> = words.collect();
+
+        match command {
+            "run" | "continue" | "c" => self.resume(),
+            "step" | "s" => self.step(args.first().and_then(|n| n.parse().ok()).unwrap_or(1)),
+            "back" | "bs" => self.back(args.first().and_then(|n| n.parse().ok()).unwrap_or(1)),
+            "reverse-continue" | "rc" => {
+                let outcome = self.machine.reverse_continue();
+                self.describe(Ok(outcome))
+            }
+            "break" | "b" => match args.first().and_then(|target| self.target(target)) {
+                Some(index) => {
+                    self.machine.set_breakpoint(index);
+                    format!("breakpoint at {}\n", self.machine.describe_location(index))
+                }
+                None => "usage: break <index|label>\n".to_string(),
+            },
+            "delete" => match args.first().and_then(|target| self.target(target)) {
+                Some(index) => {
+                    self.machine.clear_breakpoint(index);
+                    String::new()
+                }
+                None => "usage: delete <index|label>\n".to_string(),
+            },
+            "watch" => self.watch(&args),
+            "unwatch" => match args.first().and_then(|loc| parse_location(loc)) {
+                Some(ptr) if self.machine.unwatch(ptr) => String::new(),
+                Some(ptr) => format!("no watchpoint on {}\n", location_name(ptr)),
+                None => "usage: unwatch <loc>\n".to_string(),
+            },
+            "log" => self.machine.take_watch_log()
+                .into_iter()
+                .map(|hit| self.describe_hit(&hit))
+                .collect(),
+            "print" | "p" => match args.first().and_then(|loc| parse_location(loc)) {
+                Some(ptr) => format!("{} = {}\n", location_name(ptr), word_name(self.machine.watched_value(ptr))),
+                None => "usage: print <loc>\n".to_string(),
+            },
+            "regs" => self.machine.registers.iter()
+                .enumerate()
+                .map(|(index, word)| format!("r{} = {:?}\n", index, word))
+                .collect(),
+            "stack" => self.machine.stack.stack.iter()
+                .enumerate()
+                .map(|(index, word)| format!("stack:{} = {:?}\n", index, word))
+                .collect(),
+            "where" => format!("{}\n", self.where_()),
+            "help" | "h" => HELP.to_string(),
+            _ => format!("unknown command `{}`, try help\n", command),
+        }
+    }
+
+    /// Read commands until quit or the end of input
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl std::io::Write) -> Result<(), Error> {
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(cvm) ").and_then(|_| out.flush()).map_err(|_| Error::IO)?;
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+
+            let line = line.map_err(|_| Error::IO)?;
+            if matches!(line.trim(), "quit" | "q") {
+                return Ok(());
+            }
+            write!(out, "{}", self.command(&line)).map_err(|_| Error::IO)?;
+        }
+    }
+
+    /// Instruction index from a number or a label
+    fn target(&self, target: &str) -> Option<usize> {
+        target.parse().ok().or_else(|| self.machine.debug_info()?.label(target))
+    }
+
+    fn where_(&self) -> String {
+        let ip = self.machine.ip();
+        match self.machine.program.get(ip) {
+            Some(inst) => format!("{} {:?} at {}", ip, inst.inst_type, self.machine.describe_location(ip)),
+            None => format!("{} past the end of the program", ip),
+        }
+    }
+
+    fn resume(&mut self) -> String {
+        let outcome = match self.started {
+            true => self.machine.resume(),
+            false => self.machine.run(),
+        };
+        self.started = true;
+        self.describe(outcome)
+    }
+
+    fn step(&mut self, n: usize) -> String {
+        if let Err(err) = self.machine.prepare() {
+            return format!("error: {}\n", self.machine.describe_error(err));
+        }
+        self.started = true;
+
+        for _ in 0..n {
+            match self.machine.step() {
+                Ok(true) => {}
+                Ok(false) => return "program stopped\n".to_string(),
+                Err(err) => return format!("error: {}\n", self.machine.describe_error(err)),
+            }
+
+            if let Some(hit) = self.machine.watch_pause.take() {
+                return self.describe_hit(&hit);
+            }
+        }
+
+        format!("{}\n", self.where_())
+    }
+
+    fn back(&mut self, n: usize) -> String {
+        for _ in 0..n {
+            if !self.machine.step_back() {
+                return format!("start of history, {}\n", self.where_());
+            }
+        }
+
+        format!("{}\n", self.where_())
+    }
+
+    fn watch(&mut self, args: &[&str]) -> String {
+        let Some(ptr) = args.first().and_then(|loc| parse_location(loc)) else {
+            return "usage: watch <loc> [read|write|rw] [log]\n".to_string();
+        };
+
+        let mut access = Access::Write;
+        let mut action = WatchAction::Pause;
+        for arg in &args[1..] {
+            match *arg {
+                "read" => access = Access::Read,
+                "write" => access = Access::Write,
+                "rw" => access = Access::ReadWrite,
+                "log" => action = WatchAction::Log,
+                _ => return format!("unknown watch option `{}`\n", arg),
+            }
+        }
+
+        match self.machine.watch(ptr, access, action) {
+            Ok(()) => format!("watching {} for {:?}\n", location_name(ptr), access),
+            Err(err) => format!("error: {:?}\n", err),
+        }
+    }
+
+    fn describe(&mut self, outcome: Result<Outcome, Error>) -> String {
+        match outcome {
+            Ok(Outcome::Exited(code)) => format!("exited with {:?}\n", code),
+            Ok(Outcome::Halted) => format!("halted before {}\n", self.where_()),
+            Ok(Outcome::EndOfProgram) => "end of program\n".to_string(),
+            Ok(Outcome::OutOfFuel) => "out of fuel\n".to_string(),
+            Ok(Outcome::TimedOut) => "timed out\n".to_string(),
+            Ok(Outcome::Breakpoint) => format!("breakpoint at {}\n", self.where_()),
+            Ok(Outcome::WaitingForInput) => "waiting for input\n".to_string(),
+            Ok(Outcome::Watchpoint(hit)) => self.describe_hit(&hit),
+            Ok(Outcome::HistoryStart) => format!("start of history, {}\n", self.where_()),
+            Err(err) => format!("error: {}\n", self.machine.describe_error(err)),
+        }
+    }
+
+    /// e.g. `heap:3 written at main.asm:7:5: Int(0) -> Int(9)`
+    pub fn describe_hit(&self, hit: &WatchHit) -> String {
+        let mut out = String::new();
+        let place = self.machine.describe_location(hit.ip);
+        let _ = match hit.access {
+            Access::Read => writeln!(out, "{} read at {}: {}", location_name(hit.ptr), place, word_name(hit.old)),
+            _ => writeln!(
+                out,
+                "{} written at {}: {} -> {}",
+                location_name(hit.ptr), place, word_name(hit.old), word_name(hit.new),
+            ),
+        };
+        out
+    }
+}
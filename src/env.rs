@@ -0,0 +1,97 @@
+use super::*;
+
+impl Machine {
+    /// Place program arguments on the heap as char segments
+    pub fn set_args<S: AsRef<str>>(&mut self, args: &[S]) -> Result<(), Error> {
+        self.args.clear();
+        for arg in args {
+            let ptr = self.alloc_string(arg.as_ref())?;
+            self.args.push(ptr);
+        }
+
+        Ok(())
+    }
+
+    /// Allow `GetEnv` to read the variable
+    pub fn allow_env(&mut self, name: &str) {
+        self.env_allowlist.insert(name.to_string());
+    }
+
+    /// Pointer to the nth program argument
+    pub fn argv(&self, n: i64) -> Result<Pointer, Error> {
+        if n < 0 {
+            return Err(Error::SegmentationFault);
+        }
+
+        self.args.get(n as usize)
+            .copied()
+            .ok_or(Error::SegmentationFault)
+    }
+
+    /// Look up environment variable by char segment name, copying its value
+    /// to the heap. Returns None if it is not set
+    pub fn getenv(&mut self, name_ptr: Pointer) -> Result<Option<Pointer>, Error> {
+        let value: Option<String> = self.host(HostCall::GetEnv, |m| {
+            let name = m.read_string(name_ptr)?;
+            if !m.env_allowlist.contains(&name) {
+                return Err(Error::PermissionDenied);
+            }
+
+            Ok(std::env::var(&name).ok())
+        })?;
+
+        match value {
+            Some(value) => Ok(Some(self.alloc_string(&value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SharedBuffer;
+    use crate::run::Outcome;
+
+    fn run(source: &str, setup: impl FnOnce(&mut Machine)) -> (Result<Outcome, Error>, String) {
+        let mut machine = Machine::from_module(crate::asm::assemble(source, "test.asm").unwrap());
+        let out = SharedBuffer::new();
+        machine.set_output(out.clone());
+        setup(&mut machine);
+        (machine.run(), out.contents_string())
+    }
+
+    #[test]
+    fn arguments_are_char_segments() {
+        let source = "argc\nprintf \"%d:\"\nargv 1\nprintf \"%s:\"\npushi 0\nargv _\nlen _\nprint\n";
+        let (result, out) = run(source, |m| m.set_args(&["prog", "ärg"]).unwrap());
+
+        assert_eq!(result, Ok(Outcome::EndOfProgram));
+        assert_eq!(out, "2:ärg:4");
+    }
+
+    #[test]
+    fn argument_index_is_checked() {
+        let (result, _) = run("argv 2\n", |m| m.set_args(&["a", "b"]).unwrap());
+        assert_eq!(result, Err(Error::SegmentationFault));
+
+        let (result, _) = run("pushi -1\nargv _\n", |m| m.set_args(&["a"]).unwrap());
+        assert_eq!(result, Err(Error::SegmentationFault));
+    }
+
+    #[test]
+    fn getenv_needs_the_allowlist() {
+        // Cargo sets it for the test binary as well
+        let source = "getenv \"CARGO_MANIFEST_DIR\"\nprintf \"%s\"\n";
+        let (result, _) = run(source, |_| ());
+        assert_eq!(result, Err(Error::PermissionDenied));
+
+        let (result, out) = run(source, |m| m.allow_env("CARGO_MANIFEST_DIR"));
+        assert_eq!(result, Ok(Outcome::EndOfProgram));
+        assert_eq!(out, env!("CARGO_MANIFEST_DIR"));
+
+        let (result, out) = run("getenv \"CVM46_UNSET\"\nprint\n", |m| m.allow_env("CVM46_UNSET"));
+        assert_eq!(result, Ok(Outcome::EndOfProgram));
+        assert_eq!(out, "None");
+    }
+}
@@ -1,16 +1,62 @@
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
 pub enum Error { 
-    StackOverflow,
-    StackUnderflow,
-    SegmentationFault,
-    OutOfMemory,
-    IllegalInst,
-    DivByZero,
-    IllegalJmp,
-    IllegalOperandType,
-    TypeMismatch,
-    IO,
-    InvalidPointer,
-    FileNotFound,
+    StackOverflow = 0,
+    StackUnderflow = 1,
+    SegmentationFault = 2,
+    OutOfMemory = 3,
+    IllegalInst = 4,
+    DivByZero = 5,
+    IllegalJmp = 6,
+    IllegalOperandType = 7,
+    TypeMismatch = 8,
+    IO = 9,
+    InvalidPointer = 10,
+    FileNotFound = 11,
+    UnresolvedNative = 12,
+    PermissionDenied = 13,
+    TooManyOpenFiles = 14,
+    IllegalFormat = 15,
+    ParseError = 16,
+    EndOfInput = 17,
+    VerifyFailed = 18,
+    IntegerOverflow = 19,
+    OutOfFuel = 20,
+    Timeout = 21,
+    InvalidBytecode = 22,
+    ReplayDivergence = 23,
+}
+
+impl Error {
+    /// Every error, indexed by its discriminant
+    pub const ALL: &'static [Error] = {
+        use Error::*;
+        &[
+            StackOverflow, StackUnderflow, SegmentationFault, OutOfMemory, IllegalInst,
+            DivByZero, IllegalJmp, IllegalOperandType, TypeMismatch, IO, InvalidPointer,
+            FileNotFound, UnresolvedNative, PermissionDenied, TooManyOpenFiles,
+            IllegalFormat, ParseError, EndOfInput, VerifyFailed, IntegerOverflow,
+            OutOfFuel, Timeout, InvalidBytecode, ReplayDivergence,
+        ]
+    };
+
+    /// Error encoded as `error as u8`
+    pub fn from_u8(byte: u8) -> Option<Error> {
+        Error::ALL.get(byte as usize).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_match_their_index() {
+        for (index, err) in Error::ALL.iter().enumerate() {
+            assert_eq!(*err as usize, index, "{:?}", err);
+            assert_eq!(Error::from_u8(index as u8), Some(*err));
+        }
+        assert_eq!(Error::ALL.len(), Error::ReplayDivergence as usize + 1);
+        assert_eq!(Error::from_u8(Error::ALL.len() as u8), None);
+    }
 }
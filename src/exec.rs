@@ -1,60 +1,150 @@
 use super::*;
+use crate::run::Outcome;
 use crate::error::Error;
-use memory::*;
 
-#[derive(Debug, Clone)] 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)] 
+#[repr(u8)]
 pub enum InstType { 
-    Pushi,  // Push Integer
-    Pushf,  // Push Float (32-bit)
-    Pushd,  // Push Double (64-bit)
-    Pushc,  // Push Char 
-    Pushr,  // Push Register 
-    Pushs,  // Push Segment 
-    Pop,    // Pop Stack
-    Popr,   // Pop Stack and put value on register
-    Dup,    // Duplicate
-    Plus,   // Plus op
-    Sub,    // Sub op
-    Mul,    // Mul op
-    Div,    // Div op
+    Pushi = 0,       // Push Integer
+    Pushf = 1,       // Push Float (32-bit)
+    Pushd = 2,       // Push Double (64-bit)
+    Pushc = 3,       // Push Char
+    Pushr = 4,       // Push Register
+    Pushs = 5,       // Push Segment
+    Pop = 6,         // Pop Stack
+    Popr = 7,        // Pop Stack and put value on register
+    Dup = 8,         // Duplicate
+    Swap = 9,        // Swap top two
+    Over = 10,       // Copy second to top
+    Rot = 11,        // Rotate top three
+    Pick = 12,       // Copy nth to top
+    Roll = 13,       // Move nth to top
+    DropN = 14,      // Pop n
+    Plus = 15,       // Plus op
+    Sub = 16,        // Sub op
+    Mul = 17,        // Mul op
+    Div = 18,        // Div op
            
-    And,    // Bitwise And op
-    Or,     // Bitwise Or op
-    Xor,    // Bitwise Xor op
-    Not,    // Bitwise Not op
+    And = 19,        // Bitwise And op
+    Or = 20,         // Bitwise Or op
+    Xor = 21,        // Bitwise Xor op
+    Not = 22,        // Bitwise Not op
             
-    Jmp,    // Jump
-    Jeq,    // Jump if Equal
-    Jne,    // Jump if not Equal
-    Halt,   // Halt Execution 
-    Call,   // Call ip
-    Return, // Return to ip
-    Exit,   // Stop Execution
+    Jmp = 23,        // Jump
+    Jeq = 24,        // Jump if Equal
+    Jne = 25,        // Jump if not Equal
+    Halt = 26,       // Halt Execution
+    Call = 27,       // Call ip
+    Return = 28,     // Return to ip
+    Exit = 29,       // Stop Execution
            
-    Cmp,    // Compare
+    Cmp = 30,        // Compare
            
-    Alloc,  // Allocate Array on Heap
-    Free,   // Free Array on Heap
-    Set,    // Sets Element. Needs pointer to Target Element
-
-    Mov,    // Copy Value from memory into register
-    Loadr,  // Load value from Heap into register
-    Storer, // Store register value on heap
+    Alloc = 31,      // Allocate Array on Heap
+    Free = 32,       // Free Array on Heap
+    Set = 33,        // Sets Element. Needs pointer to Target Element
+    Memcpy = 34,     // Copy Words
+    Memmove = 35,    // Copy Words, may overlap
+    Memset = 36,     // Set Words
+    Memcmp = 37,     // Compare Words
+    Realloc = 38,    // Resize Array on Heap
+
+    PtrAdd = 39,     // Move Pointer forward
+    PtrSub = 40,     // Move Pointer back
+    PtrDiff = 41,    // Distance between Pointers
+    LoadIdx = 42,    // Push Element at index
+    StoreIdx = 43,   // Set Element at index
+    Len = 44,        // Push length of Array
+
+    Mov = 45,        // Copy Value from memory into register
+    Loadr = 46,      // Load value from Heap into register
+    Storer = 47,     // Store register value on heap
+    Movr = 48,       // Copy register into register
+    Load = 49,       // Load register from address in register
+    Store = 50,      // Store register at address in register
+    Addr = 51,       // Add registers
+    Subr = 52,       // Sub registers
+    Mulr = 53,       // Mul registers
+    Divr = 54,       // Div registers
+    PushAll = 55,    // Push all registers
+    PopAll = 56,     // Pop all registers
  
-    Open,   // Open File
-    Close,  // Close File
-    Readf,  // Read File
-    Writef, // Write File
-
-    Read,   // Read Stdin
-    Write,  // Write Stdout
+    Open = 57,       // Open File
+    Close = 58,      // Close File
+    Readf = 59,      // Read File
+    Writef = 60,     // Write File
+    Seek = 61,       // Move File cursor
+    Tell = 62,       // Push File cursor
+    Fsize = 63,      // Push File size
+    Exists = 64,     // Push if Path exists
+    Remove = 65,     // Remove Path
+    Rename = 66,     // Rename Path
+    Mkdir = 67,      // Make Directory
+    Listdir = 68,    // List Directory into Heap array
+
+    Read = 69,       // Read Stdin
+    Scani = 70,      // Read Integer from Stdin
+    Scanf = 71,      // Read Float from Stdin
+    Scand = 72,      // Read Double from Stdin
+    Scanc = 73,      // Read Char from Stdin
+    Reada = 74,      // Read all of Stdin
+    Eof = 75,        // Push if Stdin is at end
+    Write = 76,      // Write Stdout
+    Print = 77,      // Print top of Stack to Stdout
+    Printf = 78,     // Print formatted String to Stdout
+
+    CallNative = 79, // Call host registered function
+
+    Strlen = 80,     // Length of String
+    Strcat = 81,     // Concatenate Strings into Heap
+    Strcmp = 82,     // Compare Strings
+    Streq = 83,      // Strings are equal
+    Substr = 84,     // Copy part of String into Heap
+    Strfind = 85,    // Find String in String
+    Upper = 86,      // Uppercase copy of String
+    Lower = 87,      // Lowercase copy of String
+    Isdigit = 88,    // Char is digit
+    Isalpha = 89,    // Char is alphabetic
+    Isalnum = 90,    // Char is alphanumeric
+    Isspace = 91,    // Char is whitespace
+    Isupper = 92,    // Char is uppercase
+    Islower = 93,    // Char is lowercase
+    Atoi = 94,       // String to Integer
+    Atof = 95,       // String to Double
+    Itoa = 96,       // Number to String
+
+    Argc = 97,       // Push number of program arguments
+    Argv = 98,       // Push Pointer to program argument
+    GetEnv = 99,     // Push Pointer to environment variable
+}
 
+impl InstType {
+    /// Every instruction type, indexed by its discriminant
+    pub const ALL: &'static [InstType] = {
+        use InstType::*;
+        &[
+            Pushi, Pushf, Pushd, Pushc, Pushr, Pushs, Pop, Popr, Dup, Swap, Over, Rot, Pick,
+            Roll, DropN, Plus, Sub, Mul, Div, And, Or, Xor, Not, Jmp, Jeq, Jne, Halt, Call,
+            Return, Exit, Cmp, Alloc, Free, Set, Memcpy, Memmove, Memset, Memcmp, Realloc,
+            PtrAdd, PtrSub, PtrDiff, LoadIdx, StoreIdx, Len, Mov, Loadr, Storer, Movr, Load,
+            Store, Addr, Subr, Mulr, Divr, PushAll, PopAll, Open, Close, Readf, Writef,
+            Seek, Tell, Fsize, Exists, Remove, Rename, Mkdir, Listdir, Read, Scani, Scanf,
+            Scand, Scanc, Reada, Eof, Write, Print, Printf, CallNative, Strlen, Strcat,
+            Strcmp, Streq, Substr, Strfind, Upper, Lower, Isdigit, Isalpha, Isalnum,
+            Isspace, Isupper, Islower, Atoi, Atof, Itoa, Argc, Argv, GetEnv,
+        ]
+    };
+
+    /// Instruction type encoded as `inst_type as u8`
+    pub fn from_u8(byte: u8) -> Option<InstType> {
+        InstType::ALL.get(byte as usize).copied()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Inst {
-    inst_type: InstType,
-    operand: [Word; 2],
+    pub inst_type: InstType,
+    pub operand: [Word; 2],
 }
 
 impl Inst {
@@ -64,24 +154,55 @@ impl Inst {
 }
 
 impl Machine {
-    /// Execute whole program
-    pub fn exec(&mut self) -> Result<(), Error> {
-        while self.ip < self.program.len() && !self.halt {
-            if self.exit {
-                break;
-            }
+    /// Execute whole program and return why it stopped. Running out of
+    /// fuel or time are errors, a breakpoint, watchpoint or input that is
+    /// not ready yet is returned like any other outcome and can be resumed
+    pub fn exec(&mut self) -> Result<Outcome, Error> {
+        match self.run()? {
+            Outcome::OutOfFuel => Err(Error::OutOfFuel),
+            Outcome::TimedOut => Err(Error::Timeout),
+            outcome => Ok(outcome),
+        }
+    }
 
-            let inst = self.program[self.ip].clone();
-            self.ip += 1;
-            self.exec_inst(&inst)?;
-            if self.debug {
-                self.dump();
-            }
+    /// Execute single instruction, returns false once the program stopped
+    pub fn step(&mut self) -> Result<bool, Error> {
+        if self.ip >= self.program.len() || self.halt || self.exit.is_some() {
+            return Ok(false);
         }
-        Ok(())
+
+        let inst = self.program[self.ip].clone();
+        self.charge(inst.inst_type)?;
+        let trace = self.trace_start(&inst);
+        let watch = self.watch_start();
+        let undo = self.undo_start();
+        let ip = self.ip;
+        self.ip += 1;
+
+        let result = self.profile_inst(&inst);
+        self.cover(ip, inst.inst_type, result.is_ok());
+        if result.is_err() {
+            self.fault = Some(ip);
+        }
+        let journal = self.journal.take().unwrap_or_default();
+        if let Some(start) = watch {
+            self.watch_end(start, ip, &journal);
+        }
+        if let Some(undo) = undo {
+            self.undo_end(undo, &journal);
+        }
+        if let Some(start) = trace {
+            self.trace_end(start, &inst, &result, &journal)?;
+        }
+        result?;
+        if self.debug {
+            self.dump();
+        }
+
+        Ok(true)
     }
 
-    fn exec_inst(&mut self, inst: &Inst) -> Result<(), Error> {
+    pub fn exec_inst(&mut self, inst: &Inst) -> Result<(), Error> {
         match inst.inst_type {
             InstType::Pushi => {
                 if let Word::Int(val) = inst.operand[0] {
@@ -118,7 +239,7 @@ impl Machine {
                         return Err(Error::IllegalInst);
                     }
 
-                    let value = self.registers[reg_index];
+                    let value = self.reg(reg_index);
                     self.stack.push(value)?;
                 }
                 else {
@@ -127,9 +248,15 @@ impl Machine {
             }
             InstType::Pushs => {
                 if let Word::Ptr(ptr) = inst.operand[0] {
-                    let data_ptr = ptr.as_usize();
-                    let segment_ptr = self.stack.push_segment(&self.data[data_ptr].clone())?;
-                    let _ = self.stack.push(Word::Ptr(segment_ptr));
+                    let segment = self.data.get(ptr.as_usize())
+                        .cloned()
+                        .ok_or(Error::SegmentationFault)?;
+
+                    let segment_ptr = self.stack.push_segment(&segment)?;
+                    self.stack.push(Word::Ptr(segment_ptr))?;
+                }
+                else {
+                    return Err(Error::IllegalInst);
                 }
             }
             InstType::Pop => {
@@ -142,7 +269,8 @@ impl Machine {
                         return Err(Error::InvalidPointer)?;
                     }
 
-                    self.registers[reg_index] = self.stack.pop()?;
+                    let value = self.stack.pop()?;
+                    self.set_reg(reg_index, value);
                 }
                 else {
                     return Err(Error::InvalidPointer)?;
@@ -151,71 +279,55 @@ impl Machine {
             InstType::Dup => {
                 self.stack.dup()?;
             }
+            InstType::Swap => {
+                self.stack.swap()?;
+            }
+            InstType::Over => {
+                self.stack.over()?;
+            }
+            InstType::Rot => {
+                self.stack.rot()?;
+            }
+            InstType::Pick | InstType::Roll | InstType::DropN => {
+                let n = match inst.operand[0] {
+                    Word::Int(n) if n >= 0 => n as usize,
+                    Word::None => self.pop_len()?,
+                    _ => return Err(Error::IllegalInst),
+                };
+
+                match inst.inst_type {
+                    InstType::Pick => self.stack.pick(n)?,
+                    InstType::Roll => self.stack.roll(n)?,
+                    _ => self.stack.drop_n(n)?,
+                }
+            }
             InstType::Plus => {
                 if self.stack.sp < 2 {
                     return Err(Error::StackUnderflow);
                 }
 
-                self.binary_op(|a, b| match (a, b) {
-                    (Word::Int(a), Word::Int(b)) => Ok(Word::Int(a + b)),
-                    (Word::Float(a), Word::Float(b)) => Ok(Word::Float(a + b)),
-                    (Word::Double(a), Word::Double(b)) => Ok(Word::Double(a + b)),
-                    _ => Err(Error::IllegalInst),
-                })?;
+                self.binary_op(arith::add)?;
             }
             InstType::Sub => {
                 if self.stack.sp < 2 {
                     return Err(Error::StackUnderflow);
                 }
 
-                self.binary_op(|a, b| match (a, b) {
-                    (Word::Int(a), Word::Int(b)) => Ok(Word::Int(a - b)),
-                    (Word::Float(a), Word::Float(b)) => Ok(Word::Float(a - b)),
-                    (Word::Double(a), Word::Double(b)) => Ok(Word::Double(a - b)),
-                    _ => Err(Error::IllegalInst),
-                })?;
+                self.binary_op(arith::sub)?;
             }
             InstType::Mul => {
                 if self.stack.sp < 2 {
                     return Err(Error::StackUnderflow);
                 }
 
-                self.binary_op(|a, b| match (a, b) {
-                    (Word::Int(a), Word::Int(b)) => Ok(Word::Int(a * b)),
-                    (Word::Float(a), Word::Float(b)) => Ok(Word::Float(a * b)),
-                    (Word::Double(a), Word::Double(b)) => Ok(Word::Double(a * b)),
-                    _ => Err(Error::IllegalInst),
-                })?;
+                self.binary_op(arith::mul)?;
             }
             InstType::Div => {
                 if self.stack.sp < 2 {
                     return Err(Error::StackUnderflow);
                 }
 
-                self.binary_op(|a, b| match (a, b) {
-                    (Word::Int(a), Word::Int(b)) => {
-                        if b == 0 {
-                            Err(Error::DivByZero)
-                        } else {
-                            Ok(Word::Int(a / b))
-                        }
-                    }
-                    (Word::Float(a), Word::Float(b)) => {
-                        if b == 0.0 {
-                            Err(Error::DivByZero)
-                        } else {
-                            Ok(Word::Float(a / b))
-                        }
-                    }
-                    (Word::Double(a), Word::Double(b)) => {
-                        if b == 0.0 {
-                            Err(Error::DivByZero)
-                        } else {
-                            Ok(Word::Double(a / b))
-                        }
-                    }
-                    _ => Err(Error::IllegalInst),
-                })?;
+                self.binary_op(arith::div)?;
             }
             InstType::And => {
                 self.binary_op(|a, b| match (a, b) {
@@ -291,17 +403,25 @@ impl Machine {
             }
             InstType::Call => { 
                 if let Word::Int(addr) = inst.operand[0] {
+                    if addr < 0 || addr as usize >= self.program.len() {
+                        return Err(Error::IllegalJmp);
+                    }
+
                     self.stack.push(Word::Int(self.stack.sbp as i64))?;
                     self.stack.sbp = self.stack.sp;
 
                     self.stack.push(Word::Int(self.ip as i64))?;
                     self.ip = addr as usize;
                 }
-
+                else {
+                    return Err(Error::IllegalJmp);
+                }
             }
             InstType::Return => {
+                // Call as last instruction returns to the end of the program
                 self.ip = match self.stack.pop()? {
-                    Word::Int(addr) => addr as usize,
+                    Word::Int(addr) if addr >= 0 && addr as usize <= self.program.len() => addr as usize,
+                    Word::Int(_) => return Err(Error::IllegalJmp),
                     _ => return Err(Error::IllegalInst),
                 };
 
@@ -329,6 +449,10 @@ impl Machine {
             // Allocate space and Push Pointer on Stack
             InstType::Alloc => {
                 if let Word::Int(size) = inst.operand[0] {
+                    if size < 0 {
+                        return Err(Error::OutOfMemory);
+                    }
+
                     let ptr = self.malloc(size as usize)?;
                     self.stack.push(Word::Ptr(ptr))?;
                 }
@@ -351,15 +475,77 @@ impl Machine {
                         return Err(Error::IllegalInst);
                     }
 
-                    if let Word::Ptr(reg_ptr) = self.registers[reg_index] {
-                        let value = self.stack.pop()?;
-                        let _ = self.setelem(reg_ptr, value);
-                    }
+                    let reg_ptr = match self.reg(reg_index) {
+                        Word::Ptr(reg_ptr) => reg_ptr,
+                        _ => return Err(Error::TypeMismatch),
+                    };
+                    let value = self.stack.pop()?;
+                    self.setelem(reg_ptr, value)?;
                 }
                 else {
                     return Err(Error::IllegalInst);
                 }
             }
+            InstType::Memcpy | InstType::Memmove => {
+                let n = self.pop_len()?;
+                let src = self.pop_ptr()?;
+                let dst = self.pop_ptr()?;
+                self.memmove(dst, src, n)?;
+            }
+            InstType::Memset => {
+                let n = self.pop_len()?;
+                let value = self.stack.pop()?;
+                let dst = self.pop_ptr()?;
+                self.memset(dst, value, n)?;
+            }
+            InstType::Memcmp => {
+                let n = self.pop_len()?;
+                let b = self.pop_ptr()?;
+                let a = self.pop_ptr()?;
+                let ord = self.memcmp(a, b, n)?;
+                self.stack.push(Word::Int(ord))?;
+            }
+            InstType::Realloc => {
+                let len = self.pop_len()?;
+                let ptr = self.pop_ptr()?;
+                let new_ptr = self.realloc(ptr, len)?;
+                self.stack.push(Word::Ptr(new_ptr))?;
+            }
+            InstType::PtrAdd | InstType::PtrSub => {
+                let delta = self.pop_int()?;
+                let ptr = self.pop_ptr()?;
+
+                let delta = match inst.inst_type {
+                    InstType::PtrSub => delta.checked_neg().ok_or(Error::SegmentationFault)?,
+                    _ => delta,
+                };
+
+                let moved = self.ptr_offset(ptr, delta)?;
+                self.stack.push(Word::Ptr(moved))?;
+            }
+            InstType::PtrDiff => {
+                let b = self.pop_ptr()?;
+                let a = self.pop_ptr()?;
+                let diff = self.ptr_diff(a, b)?;
+                self.stack.push(Word::Int(diff))?;
+            }
+            InstType::LoadIdx => {
+                let index = self.pop_int()?;
+                let base = self.pop_ptr()?;
+                let value = self.load_idx(base, index)?;
+                self.stack.push(value)?;
+            }
+            InstType::StoreIdx => {
+                let value = self.stack.pop()?;
+                let index = self.pop_int()?;
+                let base = self.pop_ptr()?;
+                self.store_idx(base, index, value)?;
+            }
+            InstType::Len => {
+                let ptr = self.pointer_operand(inst.operand[0])?;
+                let len = self.segment_len(ptr)?;
+                self.stack.push(Word::Int(len as i64))?;
+            }
             InstType::Mov => {
                 if let Word::Ptr(register_ptr) = inst.operand[0] {
 
@@ -368,7 +554,7 @@ impl Machine {
                         return Err(Error::IllegalInst);
                     }
                     
-                    self.registers[reg_index] = inst.operand[1];
+                    self.set_reg(reg_index, inst.operand[1]);
                 }
                 else {
                     return Err(Error::IllegalInst);
@@ -392,7 +578,8 @@ impl Machine {
                         return Err(Error::SegmentationFault);
                     }
 
-                    self.registers[reg_index] = self.heap[heap_ptr];
+                    self.peek(Pointer::Heap(heap_ptr), 1);
+                    self.set_reg(reg_index, self.heap[heap_ptr]);
                 }
                 else {
                     return Err(Error::IllegalInst);
@@ -409,26 +596,61 @@ impl Machine {
                     }
                     
                     let heap_ptr = self.malloc(1)?;
-                    self.setelem(heap_ptr, self.registers[reg_index])?;
+                    self.setelem(heap_ptr, self.reg(reg_index))?;
 
                     // Pointer to allocated segment will be stored on register
-                    self.registers[reg_index] = Word::Ptr(heap_ptr); 
+                    self.set_reg(reg_index, Word::Ptr(heap_ptr));
                 } else {
                     return Err(Error::IllegalInst);
                 }
             }
-            InstType::Open => {
-                let file_ptr = match inst.operand[0] {
-                    Word::Ptr(ptr) => ptr,
-                    _ => return Err(Error::IllegalInst),
-                };
+            InstType::Movr => {
+                let a = self.reg_index(inst.operand[0])?;
+                let b = self.reg_index(inst.operand[1])?;
+                self.set_reg(a, self.reg(b));
+            }
+
+            // Load rA, [rB]
+            InstType::Load => {
+                let a = self.reg_index(inst.operand[0])?;
+                let b = self.reg_index(inst.operand[1])?;
+                let value = self.load_through(b)?;
+                self.set_reg(a, value);
+            }
 
+            // Store [rA], rB
+            InstType::Store => {
+                let a = self.reg_index(inst.operand[0])?;
+                let b = self.reg_index(inst.operand[1])?;
+                self.store_through(a, self.reg(b))?;
+            }
+            InstType::Addr | InstType::Subr | InstType::Mulr | InstType::Divr => {
+                let a = self.reg_index(inst.operand[0])?;
+                let b = self.reg_index(inst.operand[1])?;
+
+                let op = match inst.inst_type {
+                    InstType::Addr => arith::add,
+                    InstType::Subr => arith::sub,
+                    InstType::Mulr => arith::mul,
+                    _ => arith::div,
+                };
+                self.register_op(a, b, op)?;
+            }
+            InstType::PushAll => {
+                self.push_all()?;
+            }
+            InstType::PopAll => {
+                self.pop_all()?;
+            }
+            InstType::Open => {
                 let mode = match self.stack.pop()? {
                     Word::Int(mode) => mode,
                     _ => return Err(Error::IllegalInst)
                 };
 
-                self.open(file_ptr, mode)?; 
+                let file_ptr = self.pointer_operand(inst.operand[0])?;
+                let ptr = self.open(file_ptr, mode)?; 
+                self.stack.push(Word::Ptr(ptr))?;
             }
             InstType::Close => {
                 if let Word::Ptr(ptr) = inst.operand[0] {
@@ -439,21 +661,288 @@ impl Machine {
                 }
             }
             InstType::Readf => {
-                // TODO
+                let file_ptr = self.pointer_operand(inst.operand[0])?;
+                let segment_ptr = self.read_file(file_ptr)?;
+                self.stack.push(Word::Ptr(segment_ptr))?;
             }
             InstType::Writef => {
-                // TODO
+                let str_ptr = self.pointer_operand(inst.operand[1])?;
+                let file_ptr = self.pointer_operand(inst.operand[0])?;
+                self.write_file(file_ptr, str_ptr)?;
+            }
+            InstType::Seek => {
+                let file_ptr = self.pointer_operand(inst.operand[0])?;
+                let whence = match inst.operand[1] {
+                    Word::Int(whence) => whence,
+                    Word::None => 0,
+                    _ => return Err(Error::IllegalInst),
+                };
+
+                let offset = match self.stack.pop()? {
+                    Word::Int(offset) => offset,
+                    _ => return Err(Error::TypeMismatch),
+                };
+
+                let pos = self.seek(file_ptr, offset, whence)?;
+                self.stack.push(Word::Int(pos as i64))?;
+            }
+            InstType::Tell => {
+                let file_ptr = self.pointer_operand(inst.operand[0])?;
+                let pos = self.tell(file_ptr)?;
+                self.stack.push(Word::Int(pos as i64))?;
+            }
+            InstType::Fsize => {
+                let file_ptr = self.pointer_operand(inst.operand[0])?;
+                let size = self.file_size(file_ptr)?;
+                self.stack.push(Word::Int(size as i64))?;
+            }
+            InstType::Exists => {
+                let path_ptr = self.pointer_operand(inst.operand[0])?;
+                let exists = self.exists(path_ptr)?;
+                self.stack.push(Word::Int(exists as i64))?;
+            }
+            InstType::Remove => {
+                let path_ptr = self.pointer_operand(inst.operand[0])?;
+                self.remove(path_ptr)?;
+            }
+            InstType::Rename => {
+                let to_ptr = self.pointer_operand(inst.operand[1])?;
+                let from_ptr = self.pointer_operand(inst.operand[0])?;
+                self.rename(from_ptr, to_ptr)?;
+            }
+            InstType::Mkdir => {
+                let path_ptr = self.pointer_operand(inst.operand[0])?;
+                self.mkdir(path_ptr)?;
+            }
+            InstType::Listdir => {
+                let path_ptr = self.pointer_operand(inst.operand[0])?;
+                let arr_ptr = self.list_dir(path_ptr)?;
+                self.stack.push(Word::Ptr(arr_ptr))?;
             }
             InstType::Read => {
-                self.read()?;
+                let ptr = self.read()?;
+                self.stack.push(Word::Ptr(ptr))?;
+            }
+            InstType::Scani | InstType::Scanf | InstType::Scand => {
+                let value = self.scan(&inst.inst_type)?;
+                self.stack.push(value)?;
+            }
+            InstType::Scanc => {
+                let c = self.read_char()?;
+                self.stack.push(Word::Char(c))?;
+            }
+            InstType::Reada => {
+                let ptr = self.read_all()?;
+                self.stack.push(Word::Ptr(ptr))?;
+            }
+            InstType::Eof => {
+                let eof = self.at_eof()?;
+                self.stack.push(Word::Int(eof as i64))?;
             }
             InstType::Write => {
                 if let Word::Ptr(ptr) = inst.operand[0] {
                     self.write(ptr)?;
                 }
             }
+            InstType::Print => {
+                let value = self.stack.pop()?;
+                self.print(&format::canonical(value))?;
+            }
+            InstType::Printf => {
+                let fmt_ptr = self.pointer_operand(inst.operand[0])?;
+                let string = self.sprintf(fmt_ptr)?;
+                self.print(&string)?;
+            }
+            InstType::CallNative => {
+                let index = match inst.operand[0] {
+                    Word::Ptr(Pointer::Native(index)) => index,
+                    Word::Ptr(Pointer::Data(_)) => return Err(Error::UnresolvedNative),
+                    _ => return Err(Error::IllegalInst),
+                };
+
+                let argc = match inst.operand[1] {
+                    Word::Int(argc) if argc >= 0 => argc as usize,
+                    Word::None => 0,
+                    _ => return Err(Error::IllegalInst),
+                };
+
+                self.call_native(index, argc)?;
+            }
+            InstType::Strlen => {
+                let ptr = self.pointer_operand(inst.operand[0])?;
+                let len = self.strlen(ptr)?;
+                self.stack.push(Word::Int(len))?;
+            }
+            InstType::Strcat => {
+                let b = self.pointer_operand(inst.operand[1])?;
+                let a = self.pointer_operand(inst.operand[0])?;
+                let ptr = self.strcat(a, b)?;
+                self.stack.push(Word::Ptr(ptr))?;
+            }
+            InstType::Strcmp | InstType::Streq => {
+                let b = self.pointer_operand(inst.operand[1])?;
+                let a = self.pointer_operand(inst.operand[0])?;
+                let ord = self.strcmp(a, b)?;
+
+                let result = match inst.inst_type {
+                    InstType::Streq => (ord == 0) as i64,
+                    _ => ord,
+                };
+                self.stack.push(Word::Int(result))?;
+            }
+            InstType::Substr => {
+                let len = match self.stack.pop()? {
+                    Word::Int(len) => len,
+                    _ => return Err(Error::TypeMismatch),
+                };
+                let start = match self.stack.pop()? {
+                    Word::Int(start) => start,
+                    _ => return Err(Error::TypeMismatch),
+                };
+
+                let ptr = self.pointer_operand(inst.operand[0])?;
+                let sub = self.substr(ptr, start, len)?;
+                self.stack.push(Word::Ptr(sub))?;
+            }
+            InstType::Strfind => {
+                let needle = self.pointer_operand(inst.operand[1])?;
+                let haystack = self.pointer_operand(inst.operand[0])?;
+                let index = self.strfind(haystack, needle)?;
+                self.stack.push(Word::Int(index))?;
+            }
+            InstType::Upper | InstType::Lower => {
+                let ptr = self.pointer_operand(inst.operand[0])?;
+                let mapped = self.strmap(ptr, matches!(inst.inst_type, InstType::Upper))?;
+                self.stack.push(Word::Ptr(mapped))?;
+            }
+            InstType::Isdigit | InstType::Isalpha | InstType::Isalnum |
+            InstType::Isspace | InstType::Isupper | InstType::Islower => {
+                let c = match self.stack.pop()? {
+                    Word::Char(c) => c,
+                    _ => return Err(Error::TypeMismatch),
+                };
+
+                let result = Machine::classify(&inst.inst_type, c)?;
+                self.stack.push(Word::Int(result as i64))?;
+            }
+            InstType::Atoi => {
+                let ptr = self.pointer_operand(inst.operand[0])?;
+                let value = self.atoi(ptr)?;
+                self.stack.push(Word::Int(value))?;
+            }
+            InstType::Atof => {
+                let ptr = self.pointer_operand(inst.operand[0])?;
+                let value = self.atof(ptr)?;
+                self.stack.push(Word::Double(value))?;
+            }
+            InstType::Itoa => {
+                let value = self.stack.pop()?;
+                let ptr = self.itoa(value)?;
+                self.stack.push(Word::Ptr(ptr))?;
+            }
+            InstType::Argc => {
+                self.stack.push(Word::Int(self.args.len() as i64))?;
+            }
+            InstType::Argv => {
+                let n = match inst.operand[0] {
+                    Word::Int(n) => n,
+                    Word::None => match self.stack.pop()? {
+                        Word::Int(n) => n,
+                        _ => return Err(Error::TypeMismatch),
+                    },
+                    _ => return Err(Error::IllegalInst),
+                };
+
+                let ptr = self.argv(n)?;
+                self.stack.push(Word::Ptr(ptr))?;
+            }
+            InstType::GetEnv => {
+                let name_ptr = self.pointer_operand(inst.operand[0])?;
+                match self.getenv(name_ptr)? {
+                    Some(ptr) => self.stack.push(Word::Ptr(ptr))?,
+                    None => self.stack.push(Word::None)?,
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_returns_pauses() {
+        let program = vec![
+            Inst::new(InstType::Pushi, [Word::Int(1), Word::None]),
+            Inst::new(InstType::Exit, [Word::Int(3), Word::None]),
+        ];
+        let mut machine = Machine::new(program);
+        machine.skip_verify();
+        machine.set_breakpoint(1);
+
+        assert_eq!(machine.exec(), Ok(Outcome::Breakpoint));
+        assert_eq!(machine.resume(), Ok(Outcome::Exited(Word::Int(3))));
+    }
+
+    /// Input that never has anything ready, like a non-blocking socket
+    struct NotReady;
+
+    impl std::io::Read for NotReady {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    #[test]
+    fn exec_returns_waiting_for_input() {
+        let mut machine = Machine::new(vec![Inst::new(InstType::Scani, [Word::None, Word::None])]);
+        machine.skip_verify();
+        machine.set_input(std::io::BufReader::new(NotReady));
+
+        assert_eq!(machine.exec(), Ok(Outcome::WaitingForInput));
+        assert_eq!(machine.ip(), 0);
+
+        machine.set_input(std::io::Cursor::new(b"5\n".to_vec()));
+        assert_eq!(machine.resume(), Ok(Outcome::EndOfProgram));
+        assert_eq!(machine.stack.stack, vec![Word::Int(5)]);
+    }
+
+    #[test]
+    fn opcodes_match_their_index() {
+        for (index, inst_type) in InstType::ALL.iter().enumerate() {
+            assert_eq!(*inst_type as usize, index, "{:?}", inst_type);
+            assert_eq!(InstType::from_u8(index as u8), Some(*inst_type));
+        }
+        assert_eq!(InstType::ALL.len(), InstType::GetEnv as usize + 1);
+        assert_eq!(InstType::from_u8(InstType::ALL.len() as u8), None);
+    }
+
+    fn load(source: &str) -> Machine {
+        let mut machine = Machine::from_module(crate::asm::assemble(source, "test.asm").unwrap());
+        machine.skip_verify();
+        machine
+    }
+
+    #[test]
+    fn set_writes_through_a_register() {
+        let mut machine = load("alloc 1\npopr r0\npushc 'a'\nset r0\npushi 1\nset r0\n");
+        assert_eq!(machine.run(), Ok(Outcome::EndOfProgram));
+        assert_eq!(machine.heap[1], Word::Int(1));
+    }
+
+    #[test]
+    fn set_reports_errors() {
+        let mut machine = load("pushi 1\nset r0\n");
+        assert_eq!(machine.run(), Err(Error::TypeMismatch));
+        assert_eq!(machine.stack.stack, vec![Word::Int(1)]);
+
+        let mut machine = load("alloc 1\npopr r0\nfree heap:1\npushi 1\nset r0\n");
+        assert_eq!(machine.run(), Err(Error::SegmentationFault));
+
+        let mut machine = load("pushi 1\npushi 2\npopr r0\nset r0\n");
+        assert_eq!(machine.run(), Err(Error::TypeMismatch));
+    }
+}
@@ -0,0 +1,173 @@
+use super::*;
+use crate::backend::{io_error, FileHandle, OpenMode};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// Entry in `Machine::files`, path and mode are kept for snapshots
+pub struct OpenFile {
+    pub handle: Box<dyn FileHandle>,
+    /// Path as given by the program, before the sandbox resolved it
+    pub path: String,
+    pub mode: OpenMode,
+}
+
+impl Machine {
+    /// Resolve char segment path through the sandbox
+    pub fn sandboxed_path(&self, ptr: Pointer) -> Result<PathBuf, Error> {
+        let path = self.read_string(ptr)?;
+        self.sandbox.resolve(self.fs.as_ref(), &path)
+    }
+
+    /// Path of the directory entry itself, a final symlink is not followed
+    pub fn sandboxed_entry(&self, ptr: Pointer) -> Result<PathBuf, Error> {
+        let path = self.read_string(ptr)?;
+        self.sandbox.resolve_entry(self.fs.as_ref(), &path)
+    }
+
+    /// Open file behind Pointer::Files
+    fn file(&mut self, ptr: Pointer) -> Result<&mut Box<dyn FileHandle>, Error> {
+        match ptr {
+            Pointer::Files(id) => self.files.get_mut(&id)
+                .map(|file| &mut file.handle)
+                .ok_or(Error::FileNotFound),
+            _ => Err(Error::InvalidPointer),
+        }
+    }
+
+    /// Move file cursor, whence is 0 (start), 1 (current) or 2 (end).
+    /// Returns the new offset
+    pub fn seek(&mut self, ptr: Pointer, offset: i64, whence: i64) -> Result<u64, Error> {
+        let pos = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(Error::IllegalInst),
+        };
+
+        self.host(HostCall::Seek, |m| m.file(ptr)?.seek(pos).map_err(io_error))
+    }
+
+    /// Current offset of file cursor
+    pub fn tell(&mut self, ptr: Pointer) -> Result<u64, Error> {
+        self.host(HostCall::Tell, |m| m.file(ptr)?.stream_position().map_err(io_error))
+    }
+
+    /// Size of open file, leaves the cursor where it was
+    pub fn file_size(&mut self, ptr: Pointer) -> Result<u64, Error> {
+        self.host(HostCall::FileSize, |m| {
+            let file = m.file(ptr)?;
+            let pos = file.stream_position().map_err(io_error)?;
+            let size = file.seek(SeekFrom::End(0)).map_err(io_error)?;
+            file.seek(SeekFrom::Start(pos)).map_err(io_error)?;
+            Ok(size)
+        })
+    }
+
+    /// Read line from file onto the stack, returns pointer to the segment
+    pub fn read_file(&mut self, ptr: Pointer) -> Result<Pointer, Error> {
+        let line: String = self.host(HostCall::ReadFile, |m| {
+            let file = m.file(ptr)?;
+            let mut bytes = Vec::new();
+            let mut chunk = [0u8; 256];
+
+            loop {
+                let n = file.read(&mut chunk).map_err(io_error)?;
+                if n == 0 {
+                    break;
+                }
+
+                if let Some(i) = chunk[..n].iter().position(|&b| b == b'\n') {
+                    bytes.extend_from_slice(&chunk[..i]);
+                    // Leave the cursor right after the newline
+                    file.seek(SeekFrom::Current(i as i64 + 1 - n as i64)).map_err(io_error)?;
+                    break;
+                }
+
+                bytes.extend_from_slice(&chunk[..n]);
+            }
+
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        })?;
+
+        let str_arr: Vec<Word> = line
+            .chars()
+            .map(Word::Char)
+            .collect();
+
+        self.stack.push_segment(&str_arr)
+    }
+
+    /// Write char segment to file
+    pub fn write_file(&mut self, ptr: Pointer, str_ptr: Pointer) -> Result<(), Error> {
+        self.host(HostCall::WriteFile, |m| {
+            let string = m.read_string(str_ptr)?;
+            m.file(ptr)?.write_all(string.as_bytes()).map_err(io_error)
+        })
+    }
+
+    /// Needs `allow_read`, like `ListDir`
+    pub fn exists(&mut self, path_ptr: Pointer) -> Result<bool, Error> {
+        self.host(HostCall::Exists, |m| {
+            if !m.sandbox.allow_read {
+                return Err(Error::PermissionDenied);
+            }
+
+            let path = m.sandboxed_path(path_ptr)?;
+            Ok(m.fs.exists(&path))
+        })
+    }
+
+    /// Remove file or empty directory
+    pub fn remove(&mut self, path_ptr: Pointer) -> Result<(), Error> {
+        self.host(HostCall::Remove, |m| {
+            if !m.sandbox.allow_modify {
+                return Err(Error::PermissionDenied);
+            }
+
+            let path = m.sandboxed_entry(path_ptr)?;
+            m.fs.remove(&path).map_err(io_error)
+        })
+    }
+
+    pub fn rename(&mut self, from_ptr: Pointer, to_ptr: Pointer) -> Result<(), Error> {
+        self.host(HostCall::Rename, |m| {
+            if !m.sandbox.allow_modify {
+                return Err(Error::PermissionDenied);
+            }
+
+            let from = m.sandboxed_entry(from_ptr)?;
+            let to = m.sandboxed_entry(to_ptr)?;
+            m.fs.rename(&from, &to).map_err(io_error)
+        })
+    }
+
+    pub fn mkdir(&mut self, path_ptr: Pointer) -> Result<(), Error> {
+        self.host(HostCall::Mkdir, |m| {
+            if !m.sandbox.allow_modify {
+                return Err(Error::PermissionDenied);
+            }
+
+            let path = m.sandboxed_path(path_ptr)?;
+            m.fs.create_dir(&path).map_err(io_error)
+        })
+    }
+
+    /// List directory into a heap array of pointers to char segments
+    pub fn list_dir(&mut self, path_ptr: Pointer) -> Result<Pointer, Error> {
+        let names: Vec<String> = self.host(HostCall::ListDir, |m| {
+            if !m.sandbox.allow_read {
+                return Err(Error::PermissionDenied);
+            }
+
+            let path = m.sandboxed_path(path_ptr)?;
+            m.fs.read_dir(&path).map_err(io_error)
+        })?;
+
+        let mut entries = Vec::with_capacity(names.len());
+        for name in names {
+            entries.push(Word::Ptr(self.alloc_string(&name)?));
+        }
+
+        self.alloc_segment(&entries)
+    }
+}
@@ -0,0 +1,256 @@
+use super::*;
+use crate::utils::to_string;
+use std::io::Write;
+
+/// Canonical text form of a Word, used by `Print`
+pub fn canonical(word: Word) -> String {
+    match word {
+        Word::Int(val) => val.to_string(),
+        Word::Float(val) => format!("{:?}", val),
+        Word::Double(val) => format!("{:?}", val),
+        Word::Char(val) => val.to_string(),
+        Word::Ptr(ptr) => format!("{:?}", ptr),
+        Word::Free => "Free".to_string(),
+        Word::None => "None".to_string(),
+    }
+}
+
+/// Placeholder in a `Printf` format string: %[-0][width][.precision]conv
+#[derive(Debug)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: char,
+}
+
+enum Piece {
+    Text(String),
+    Spec(Spec),
+}
+
+/// Max width and precision of a placeholder
+const MAX_WIDTH: usize = 4096;
+
+fn parse_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<usize, Error> {
+    let mut number = 0;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = number * 10 + digit as usize;
+        if number > MAX_WIDTH {
+            return Err(Error::IllegalFormat);
+        }
+        chars.next();
+    }
+
+    Ok(number)
+}
+
+fn parse(fmt: &str) -> Result<Vec<Piece>, Error> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = fmt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            text.push('%');
+            continue;
+        }
+
+        let mut spec = Spec { left: false, zero: false, width: 0, precision: None, conv: ' ' };
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        spec.width = parse_number(&mut chars)?;
+
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(parse_number(&mut chars)?);
+        }
+
+        spec.conv = match chars.next() {
+            Some(conv @ ('d' | 'f' | 'c' | 's' | 'x' | 'X' | 'p' | 'v')) => conv,
+            _ => return Err(Error::IllegalFormat),
+        };
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(&mut text)));
+        }
+        pieces.push(Piece::Spec(spec));
+    }
+
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+
+    Ok(pieces)
+}
+
+/// Number of stack values a format string consumes
+pub fn placeholder_count(fmt: &str) -> Result<usize, Error> {
+    Ok(parse(fmt)?.iter().filter(|p| matches!(p, Piece::Spec(_))).count())
+}
+
+impl Machine {
+    /// Format a single value according to its placeholder
+    fn format_spec(&self, spec: &Spec, value: Word) -> Result<String, Error> {
+        let body = match (spec.conv, value) {
+            ('d', Word::Int(val)) => val.to_string(),
+            ('f', Word::Float(val)) => format!("{:.*}", spec.precision.unwrap_or(6), val),
+            ('f', Word::Double(val)) => format!("{:.*}", spec.precision.unwrap_or(6), val),
+            ('f', Word::Int(val)) => format!("{:.*}", spec.precision.unwrap_or(6), val as f64),
+            ('x', Word::Int(val)) => format!("{:x}", val),
+            ('X', Word::Int(val)) => format!("{:X}", val),
+            ('c', Word::Char(val)) => val.to_string(),
+            ('s', Word::Ptr(ptr)) => {
+                let string = to_string(&self.read_arr(ptr)?)?;
+                match spec.precision {
+                    Some(precision) => string.chars().take(precision).collect(),
+                    None => string,
+                }
+            }
+            ('p', Word::Ptr(ptr)) => format!("{:?}", ptr),
+            ('v', value) => canonical(value),
+            _ => return Err(Error::TypeMismatch),
+        };
+
+        let len = body.chars().count();
+        if len >= spec.width {
+            return Ok(body);
+        }
+
+        let padding = spec.width - len;
+        Ok(if spec.left {
+            format!("{}{}", body, " ".repeat(padding))
+        } 
+        else if spec.zero && matches!(spec.conv, 'd' | 'f' | 'x' | 'X') {
+            // Zeros go between the sign and the digits
+            match body.strip_prefix('-') {
+                Some(digits) => format!("-{}{}", "0".repeat(padding), digits),
+                None => format!("{}{}", "0".repeat(padding), body),
+            }
+        } 
+        else {
+            format!("{}{}", " ".repeat(padding), body)
+        })
+    }
+
+    /// Format string from char segment, consuming one stack value per
+    /// placeholder. Values are used in the order they were pushed
+    pub fn sprintf(&mut self, fmt_ptr: Pointer) -> Result<String, Error> {
+        let fmt = to_string(&self.read_arr(fmt_ptr)?)?;
+        let pieces = parse(&fmt)?;
+
+        let argc = pieces.iter().filter(|p| matches!(p, Piece::Spec(_))).count();
+        if self.stack.sp < argc {
+            return Err(Error::StackUnderflow);
+        }
+
+        let mut args = Vec::with_capacity(argc);
+        for _ in 0..argc {
+            args.push(self.stack.pop()?);
+        }
+
+        let mut out = String::new();
+        for piece in &pieces {
+            match piece {
+                Piece::Text(text) => out.push_str(text),
+                Piece::Spec(spec) => {
+                    let value = args.pop().ok_or(Error::StackUnderflow)?;
+                    out.push_str(&self.format_spec(spec, value)?);
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Write string to stdout
+    pub fn print(&mut self, string: &str) -> Result<(), Error> {
+        self.reach_host();
+        write!(self.output, "{}", string)
+            .and_then(|_| self.output.flush())
+            .map_err(|_| Error::IO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprintf(fmt: &str, args: &[Word]) -> Result<String, Error> {
+        let mut machine = Machine::new(Vec::new());
+        let fmt = machine.alloc_string(fmt).unwrap();
+        for &arg in args {
+            machine.stack.push(arg).unwrap();
+        }
+        machine.sprintf(fmt)
+    }
+
+    #[test]
+    fn width_pads_left_or_right() {
+        assert_eq!(sprintf("[%5d]", &[Word::Int(42)]).unwrap(), "[   42]");
+        assert_eq!(sprintf("[%-5d]", &[Word::Int(42)]).unwrap(), "[42   ]");
+        assert_eq!(sprintf("[%2d]", &[Word::Int(12345)]).unwrap(), "[12345]");
+        assert_eq!(sprintf("[%3c]", &[Word::Char('é')]).unwrap(), "[  é]");
+    }
+
+    #[test]
+    fn precision_of_floats_and_strings() {
+        let mut machine = Machine::new(Vec::new());
+        let fmt = machine.alloc_string("%.2f %.0f %f %.3s").unwrap();
+        let string = machine.alloc_string("abcdef").unwrap();
+        for arg in [Word::Double(1.23456), Word::Float(2.5), Word::Int(1), Word::Ptr(string)] {
+            machine.stack.push(arg).unwrap();
+        }
+        assert_eq!(machine.sprintf(fmt).unwrap(), "1.23 2 1.000000 abc");
+    }
+
+    #[test]
+    fn zero_padding_goes_after_the_sign() {
+        assert_eq!(sprintf("%05d", &[Word::Int(-42)]).unwrap(), "-0042");
+        assert_eq!(sprintf("%05d", &[Word::Int(42)]).unwrap(), "00042");
+        assert_eq!(sprintf("%08.2f", &[Word::Double(-1.5)]).unwrap(), "-0001.50");
+        assert_eq!(sprintf("%-05d", &[Word::Int(-4)]).unwrap(), "-4   ");
+        assert_eq!(sprintf("%04x", &[Word::Int(255)]).unwrap(), "00ff");
+    }
+
+    #[test]
+    fn percent_sign_is_escaped() {
+        assert_eq!(sprintf("100%%", &[]).unwrap(), "100%");
+        assert_eq!(sprintf("%d%%", &[Word::Int(5)]).unwrap(), "5%");
+        assert_eq!(placeholder_count("%% %d %%"), Ok(1));
+        assert_eq!(sprintf("%", &[]), Err(Error::IllegalFormat));
+        assert_eq!(sprintf("%q", &[Word::Int(1)]), Err(Error::IllegalFormat));
+    }
+
+    #[test]
+    fn arguments_must_match_the_placeholders() {
+        let mut machine = Machine::new(Vec::new());
+        let fmt = machine.alloc_string("%d %d").unwrap();
+        machine.stack.push(Word::Int(1)).unwrap();
+        assert_eq!(machine.sprintf(fmt), Err(Error::StackUnderflow));
+        assert_eq!(machine.stack.sp, 1);
+
+        // Values below the consumed ones stay on the stack
+        machine.stack.push(Word::Int(2)).unwrap();
+        machine.stack.push(Word::Int(3)).unwrap();
+        assert_eq!(machine.sprintf(fmt).unwrap(), "2 3");
+        assert_eq!(machine.stack.sp, 1);
+
+        assert_eq!(sprintf("%d", &[Word::Char('a')]), Err(Error::TypeMismatch));
+        assert_eq!(sprintf("%s", &[Word::Int(1)]), Err(Error::TypeMismatch));
+    }
+}
@@ -0,0 +1,135 @@
+use super::*;
+use std::time::{Duration, Instant};
+
+/// Steps between checks of the wall-clock deadline
+const DEADLINE_INTERVAL: u64 = 1024;
+
+impl Machine {
+    /// Limit the instructions the program may execute, None is unlimited
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Give a program that ran out more fuel before resuming it
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(fuel));
+    }
+
+    /// Fuel left, None if unlimited
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Fuel an instruction type costs, every instruction costs 1 by default
+    pub fn set_cost(&mut self, inst_type: InstType, cost: u64) {
+        self.costs.insert(inst_type, cost);
+    }
+
+    /// Stop execution once the timeout has passed from now. The clock is
+    /// read before the next instruction and then every DEADLINE_INTERVAL
+    /// instructions, so a program may run a little past it
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.set_deadline(Some(Instant::now() + timeout));
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.deadline_countdown = 0;
+    }
+
+    /// Instructions executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Take the cost of the instruction from the budget, before it executes.
+    /// Fails without side effects so execution can be resumed
+    pub fn charge(&mut self, inst_type: InstType) -> Result<(), Error> {
+        let check_clock = self.deadline_countdown == 0;
+        if check_clock && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Error::Timeout);
+        }
+
+        if let Some(fuel) = self.fuel {
+            let cost = self.costs.get(&inst_type).copied().unwrap_or(1);
+            if fuel < cost {
+                return Err(Error::OutOfFuel);
+            }
+            self.fuel = Some(fuel - cost);
+        }
+
+        if self.deadline.is_some() {
+            self.deadline_countdown = match check_clock {
+                true => DEADLINE_INTERVAL - 1,
+                false => self.deadline_countdown - 1,
+            };
+        }
+
+        self.steps += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::run::Outcome;
+
+    /// Counts down from 3 in r0 through a loop of 4 instructions
+    const LOOP: &str = "  pushi 3\n  popr r0\nloop: pushr r0\n  pushi 1\n  sub\n  dup\n  popr r0\n  \
+                        pushi 0\n  cmp\n  jne loop\n  exit 0\n";
+
+    fn machine() -> Machine {
+        let mut machine = Machine::from_module(assemble(LOOP, "test.asm").unwrap());
+        machine.skip_verify();
+        machine
+    }
+
+    #[test]
+    fn runs_out_of_fuel_before_the_instruction() {
+        let mut machine = machine();
+        machine.set_fuel(Some(5));
+        assert_eq!(machine.run(), Ok(Outcome::OutOfFuel));
+        assert_eq!(machine.steps(), 5);
+        assert_eq!(machine.ip(), 5);
+        assert_eq!(machine.fuel(), Some(0));
+    }
+
+    #[test]
+    fn costs_are_charged_per_instruction_type() {
+        let mut machine = machine();
+        machine.set_cost(InstType::Cmp, 10);
+        machine.set_fuel(Some(12));
+
+        // pushi popr pushr pushi sub dup popr pushi leave 4, less than cmp
+        assert_eq!(machine.run(), Ok(Outcome::OutOfFuel));
+        assert_eq!(machine.ip(), 8);
+        assert_eq!(machine.fuel(), Some(4));
+    }
+
+    #[test]
+    fn resumes_after_add_fuel() {
+        let mut machine = machine();
+        machine.set_fuel(Some(7));
+        assert_eq!(machine.run(), Ok(Outcome::OutOfFuel));
+
+        machine.add_fuel(100);
+        assert_eq!(machine.resume(), Ok(Outcome::Exited(Word::Int(0))));
+        assert_eq!(machine.registers[0], Word::Int(0));
+        assert_eq!(machine.steps(), 2 + 3 * 8 + 1);
+    }
+
+    #[test]
+    fn deadline_set_mid_run_is_checked_next() {
+        let mut machine = machine();
+        machine.prepare().unwrap();
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+
+        machine.set_deadline(Some(Instant::now()));
+        assert_eq!(machine.step(), Err(Error::Timeout));
+        assert_eq!(machine.steps(), 3);
+    }
+}
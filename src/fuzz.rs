@@ -0,0 +1,197 @@
+use super::*;
+use crate::backend::{FileHandle, OpenMode, SharedBuffer};
+use crate::trace::Tracer;
+use crate::watch::{Access, WatchAction};
+
+use std::io::{self, Cursor};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+const PUSHES: &[InstType] = {
+    use InstType::*;
+    &[Pushi, Pushi, Pushf, Pushd, Pushc, Pushs, Pushr, Dup, Alloc]
+};
+
+/// Steps a single program may run, random jumps loop forever
+const MAX_STEPS: usize = 1000;
+
+/// xorshift64, good enough to explore the instruction space reproducibly
+struct Rng(u64);
+
+impl Rng {
+    /// Seeds are scrambled, xorshift of nearby seeds starts out alike
+    fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+/// Filesystem that keeps nothing, so fuzzed programs cannot touch the host
+struct NullFs;
+
+impl FileSystem for NullFs {
+    fn open(&mut self, _path: &Path, _mode: OpenMode) -> io::Result<Box<dyn FileHandle>> {
+        Ok(Box::new(Cursor::new(b"fuzz\n42 1.5".to_vec())))
+    }
+}
+
+fn random_word(rng: &mut Rng, program_len: usize) -> Word {
+    let index = |rng: &mut Rng| rng.pick(&[0, 1, 2, 3, 7, 8, 100, usize::MAX]);
+
+    match rng.below(12) {
+        0 | 1 => Word::None,
+        2 => Word::Int(rng.pick(&[0, 1, -1, 2, 3, i64::MAX, i64::MIN])),
+        3 => Word::Int(rng.below(program_len + 1) as i64),
+        4 => Word::Float(rng.pick(&[0.0, 1.5, -2.0, f32::NAN, f32::INFINITY])),
+        5 => Word::Double(rng.pick(&[0.0, 2.5, -1.0, f64::NAN, f64::MAX])),
+        6 => Word::Char(rng.pick(&['a', 'Z', '0', ' ', '%', '\n', 'é'])),
+        7 => Word::Ptr(Pointer::Register(index(rng))),
+        8 => Word::Ptr(Pointer::Stack(index(rng))),
+        9 => Word::Ptr(Pointer::Heap(index(rng))),
+        10 => Word::Ptr(Pointer::Data(rng.below(4))),
+        _ => Word::Ptr(rng.pick(&[Pointer::Files(0), Pointer::Files(1), Pointer::Native(0)])),
+    }
+}
+
+/// Operands the instruction expects, so programs get past operand checks
+fn typed_operands(rng: &mut Rng, inst_type: InstType, program_len: usize) -> [Word; 2] {
+    use InstType::*;
+
+    let int = |rng: &mut Rng| Word::Int(rng.pick(&[0, 1, 2, 3, -1, i64::MAX, i64::MIN]));
+    let register = |rng: &mut Rng| Word::Ptr(Pointer::Register(rng.below(9)));
+    let target = |rng: &mut Rng| Word::Int(rng.below(program_len) as i64);
+
+    match inst_type {
+        Pushi | Alloc | Pick | Roll | DropN | Argv => [int(rng), Word::None],
+        Pushf => [Word::Float(rng.pick(&[0.0, 1.5, f32::MAX, f32::NAN])), Word::None],
+        Pushd => [Word::Double(rng.pick(&[0.0, 2.5, f64::MAX, f64::NAN])), Word::None],
+        Pushc => [Word::Char(rng.pick(&['a', '7', ' ', 'é'])), Word::None],
+        Pushs => [Word::Ptr(Pointer::Data(rng.below(3))), Word::None],
+        Pushr | Popr | Set | Storer => [register(rng), Word::None],
+        Mov => [register(rng), random_word(rng, program_len)],
+        Movr | Load | Store | Addr | Subr | Mulr | Divr => [register(rng), register(rng)],
+        Jmp | Jeq | Jne | Call => [target(rng), Word::None],
+        CallNative => [Word::Ptr(Pointer::Data(1)), Word::Int(rng.below(3) as i64)],
+        _ => [random_word(rng, program_len), random_word(rng, program_len)],
+    }
+}
+
+fn random_program(rng: &mut Rng) -> Vec<Inst> {
+    let len = 1 + rng.below(32);
+    (0..len)
+        .map(|_| {
+            // Favour pushes so later instructions find operands on the stack
+            let inst_type = match rng.below(3) {
+                0 => rng.pick(PUSHES),
+                _ => rng.pick(InstType::ALL),
+            };
+            let operand = match rng.below(5) {
+                0 => [random_word(rng, len), random_word(rng, len)],
+                _ => typed_operands(rng, inst_type, len),
+            };
+            Inst::new(inst_type, operand)
+        })
+        .collect()
+}
+
+/// Unverified machine with everything an instruction could reach set up
+fn fuzz_machine(program: Vec<Inst>) -> Machine {
+    let mut machine = Machine::new(program);
+    machine.skip_verify();
+    machine.set_input(Cursor::new(b"12 3.5 -7\nhello world\n".to_vec()));
+    machine.set_output(SharedBuffer::new());
+    machine.set_fs(NullFs);
+    machine.data = vec![
+        "%d %5.2f %s %x %c".chars().map(Word::Char).collect(),
+        "name".chars().map(Word::Char).collect(),
+        vec![Word::Int(1), Word::Float(2.0)],
+    ];
+    machine.register_native("name", |_, args| Ok(args.to_vec()));
+    let _ = machine.set_args(&["fuzz"]);
+    machine
+}
+
+/// Turn on the tracer, profiler, coverage, watchpoints and history, so
+/// their bookkeeping runs on the fuzzed instructions too
+fn instrument(machine: &mut Machine, rng: &mut Rng) {
+    machine.set_tracer(Some(Tracer::new(SharedBuffer::new())));
+    machine.enable_profiler();
+    machine.enable_coverage();
+    machine.record_history(MAX_STEPS);
+
+    let index = |rng: &mut Rng| rng.pick(&[0, 1, 2, 3, 7]);
+    let access = rng.pick(&[Access::Read, Access::Write, Access::ReadWrite]);
+    let _ = machine.watch(Pointer::Register(rng.below(8)), access, WatchAction::Log);
+    let _ = machine.watch(Pointer::Heap(index(rng)), access, WatchAction::Pause);
+    let _ = machine.watch(Pointer::Stack(index(rng)), access, WatchAction::Log);
+}
+
+/// Run random programs and check that none of them panics the host, every
+/// fourth one instrumented and stepped back to its start afterwards.
+/// Returns the seeds of the panicking programs
+fn fuzz(iterations: usize, seed: u64) -> Vec<u64> {
+    let mut failures = Vec::new();
+
+    for i in 0..iterations {
+        let program_seed = seed.wrapping_add(i as u64);
+        let mut rng = Rng::new(program_seed);
+        let program = random_program(&mut rng);
+        let instrumented = i % 4 == 0;
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut machine = fuzz_machine(program.clone());
+            if instrumented {
+                instrument(&mut machine, &mut rng);
+            }
+            if machine.link().is_err() {
+                return;
+            }
+
+            // Keep going past errors, the ip is already on the next instruction
+            for _ in 0..MAX_STEPS {
+                if let Ok(false) = machine.step() {
+                    break;
+                }
+            }
+
+            let _ = machine.profile_report();
+            let _ = machine.coverage_report();
+            while machine.step_back() {}
+        }));
+
+        if result.is_err() {
+            eprintln!("panic with seed {}: {:?}", program_seed, program);
+            failures.push(program_seed);
+        }
+    }
+
+    failures
+}
+
+/// CVM46_FUZZ sets the number of programs, e.g. for a longer release run
+#[test]
+fn random_programs_do_not_panic() {
+    let iterations = std::env::var("CVM46_FUZZ")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(5000);
+
+    assert_eq!(fuzz(iterations, 46), Vec::<u64>::new());
+}
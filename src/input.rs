@@ -0,0 +1,157 @@
+use super::*;
+use std::io::Read;
+
+impl Machine {
+    /// True if there is no input left
+    pub fn at_eof(&mut self) -> Result<bool, Error> {
+        self.host(HostCall::Eof, |m| {
+            let buf = m.input.fill_buf().map_err(|_| Error::IO)?;
+            Ok(buf.is_empty())
+        })
+    }
+
+    /// Consume whitespace, leaving the next char in the input
+    fn skip_whitespace(&mut self) -> Result<(), Error> {
+        loop {
+            let buf = self.input.fill_buf().map_err(|_| Error::IO)?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+
+            let len = buf.len();
+            let n = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+            self.input.consume(n);
+
+            if n < len {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Next whitespace delimited token
+    fn read_token(&mut self) -> Result<String, Error> {
+        self.skip_whitespace()?;
+
+        let mut bytes = Vec::new();
+        loop {
+            let buf = self.input.fill_buf().map_err(|_| Error::IO)?;
+            if buf.is_empty() {
+                break;
+            }
+
+            let len = buf.len();
+            let n = buf.iter().take_while(|b| !b.is_ascii_whitespace()).count();
+            bytes.extend_from_slice(&buf[..n]);
+            self.input.consume(n);
+
+            if n < len {
+                break;
+            }
+        }
+
+        if bytes.is_empty() {
+            return Err(Error::EndOfInput);
+        }
+
+        String::from_utf8(bytes).map_err(|_| Error::ParseError)
+    }
+
+    /// Parse Int, Float or Double from the next token, like the matching
+    /// Push instruction would push it
+    pub fn scan(&mut self, kind: &InstType) -> Result<Word, Error> {
+        let token: String = self.host(HostCall::Token, Machine::read_token)?;
+
+        match kind {
+            InstType::Scani => token.parse().map(Word::Int).map_err(|_| Error::ParseError),
+            InstType::Scanf => token.parse().map(Word::Float).map_err(|_| Error::ParseError),
+            InstType::Scand => token.parse().map(Word::Double).map_err(|_| Error::ParseError),
+            _ => Err(Error::IllegalInst),
+        }
+    }
+
+    /// Read single char
+    pub fn read_char(&mut self) -> Result<char, Error> {
+        self.host(HostCall::Char, Machine::read_char_input)
+    }
+
+    fn read_char_input(&mut self) -> Result<char, Error> {
+        let mut bytes = [0u8; 4];
+        if self.input.read(&mut bytes[..1]).map_err(|_| Error::IO)? == 0 {
+            return Err(Error::EndOfInput);
+        }
+
+        // Length of the UTF-8 sequence from its leading byte
+        let len = match bytes[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Err(Error::ParseError),
+        };
+
+        self.input.read_exact(&mut bytes[1..len]).map_err(|_| Error::ParseError)?;
+        std::str::from_utf8(&bytes[..len])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .ok_or(Error::ParseError)
+    }
+
+    /// Read all remaining input onto the stack, returns pointer to the segment
+    pub fn read_all(&mut self) -> Result<Pointer, Error> {
+        let buffer: String = self.host(HostCall::ReadAll, |m| {
+            let mut buffer = String::new();
+            m.input.read_to_string(&mut buffer).map_err(|_| Error::IO)?;
+            Ok(buffer)
+        })?;
+
+        let str_arr: Vec<Word> = buffer.chars().map(Word::Char).collect();
+        self.stack.push_segment(&str_arr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn with_input(input: &[u8]) -> Machine {
+        let mut machine = Machine::new(Vec::new());
+        machine.set_input(Cursor::new(input.to_vec()));
+        machine
+    }
+
+    #[test]
+    fn scan_parses_whitespace_separated_tokens() {
+        let mut machine = with_input(b"  -7\n2.5\t1e3 x");
+        assert_eq!(machine.scan(&InstType::Scani), Ok(Word::Int(-7)));
+        assert_eq!(machine.scan(&InstType::Scanf), Ok(Word::Float(2.5)));
+        assert_eq!(machine.scan(&InstType::Scand), Ok(Word::Double(1000.0)));
+        assert_eq!(machine.scan(&InstType::Scani), Err(Error::ParseError));
+        assert_eq!(machine.scan(&InstType::Scani), Err(Error::EndOfInput));
+    }
+
+    #[test]
+    fn read_char_decodes_utf8() {
+        let mut machine = with_input("é\n".as_bytes());
+        assert_eq!(machine.read_char(), Ok('é'));
+        assert_eq!(machine.read_char(), Ok('\n'));
+        assert_eq!(machine.read_char(), Err(Error::EndOfInput));
+
+        // Stray continuation byte, then a sequence cut short
+        let mut machine = with_input(b"\x80");
+        assert_eq!(machine.read_char(), Err(Error::ParseError));
+        let mut machine = with_input(b"\xc3");
+        assert_eq!(machine.read_char(), Err(Error::ParseError));
+    }
+
+    #[test]
+    fn read_all_empties_the_input() {
+        let mut machine = with_input(b"4 rest\nof it");
+        assert_eq!(machine.at_eof(), Ok(false));
+        assert_eq!(machine.scan(&InstType::Scani), Ok(Word::Int(4)));
+
+        let ptr = machine.read_all().unwrap();
+        assert_eq!(machine.read_string(ptr).unwrap(), " rest\nof it");
+        assert_eq!(machine.at_eof(), Ok(true));
+    }
+}
@@ -3,13 +3,54 @@ pub mod exec;
 pub mod utils; 
 pub mod memory;
 pub mod stack;
+pub mod native;
+pub mod backend;
+pub mod sandbox;
+pub mod files;
+pub mod env;
+pub mod format;
+pub mod input;
+pub mod string;
+pub mod arith;
+pub mod registers;
+pub mod verify;
+#[cfg(test)]
+mod fuzz;
+pub mod fuel;
+pub mod run;
+pub mod bytecode;
+pub mod snapshot;
+pub mod replay;
+pub mod trace;
+pub mod profile;
+pub mod source_map;
+pub mod coverage;
+pub mod debug_info;
+pub mod asm;
+pub mod watch;
+pub mod debugger;
+pub mod reverse;
 
 use error::Error;
 use exec::*;
 use stack::Stack;
+use native::NativeFn;
+use backend::{FileSystem, HostFs};
+use files::OpenFile;
+use replay::{HostCall, IoMode};
+use trace::Tracer;
+use profile::Profiler;
+use debug_info::DebugInfo;
+use coverage::Coverage;
+use watch::{Watchpoint, WatchHit};
+use reverse::History;
+use sandbox::Sandbox;
 
-use std::collections::HashMap;
-use std::fs::File;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::rc::Rc;
+use std::time::Instant;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Pointer {
@@ -18,6 +59,7 @@ pub enum Pointer {
     Heap(usize),
     Files(usize),
     Data(usize),
+    Native(usize),
 }
 
 impl Pointer {
@@ -28,9 +70,10 @@ impl Pointer {
             Pointer::Heap(v) => v,
             Pointer::Files(v) => v,
             Pointer::Data(v) => v,
+            Pointer::Native(v) => v,
         };
 
-        return *value;
+        *value
     }
 }
 
@@ -46,7 +89,7 @@ pub enum Word {
 }
 
 pub struct Machine {
-    registers: [Word; 8],
+    registers: Vec<Word>,
     data: Vec<Vec<Word>>,
 
     stack: Stack,
@@ -54,20 +97,63 @@ pub struct Machine {
     heap: Vec<Word>,
     hp: usize,
 
-    files: HashMap<usize, File>,
+    files: HashMap<usize, OpenFile>,
     file_id_counter: usize,
 
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    fs: Box<dyn FileSystem>,
+    sandbox: Sandbox,
+
+    natives: Vec<(String, Rc<NativeFn>)>,
+
+    args: Vec<Pointer>,
+    env_allowlist: HashSet<String>,
+
+    fuel: Option<u64>,
+    costs: HashMap<InstType, u64>,
+    deadline: Option<Instant>,
+    /// Instructions left until the deadline is checked again
+    deadline_countdown: u64,
+    steps: u64,
+
+    io_mode: IoMode,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    debug_info: Option<DebugInfo>,
+    /// Instruction that returned the last error
+    fault: Option<usize>,
+    /// Old values of the words the current instruction writes
+    journal: Option<Vec<(Pointer, Option<Word>)>>,
+    /// Words the current instruction reads through pointers or registers
+    reads: RefCell<Option<Vec<(Pointer, usize)>>>,
+    /// Bounds of the heap segments, parsed from the headers when needed
+    heap_segments: RefCell<Option<Vec<(usize, usize)>>>,
+    watchpoints: Vec<Watchpoint>,
+    watch_log: Vec<WatchHit>,
+    /// Hit of a pausing watchpoint, reported by run()
+    watch_pause: Option<WatchHit>,
+    history: Option<History>,
+
     ip: usize,
     program: Vec<Inst>,
-    exit: bool,
+    exit: Option<Word>,
+    breakpoints: HashSet<usize>,
     halt: bool,
     debug: bool,
+    verified: bool,
 }
 
 impl Machine {
-    fn new(program: Vec<Inst>) -> Self {
+    pub fn new(program: Vec<Inst>) -> Self {
+        Machine::with_registers(program, 8)
+    }
+
+    /// Machine with register_count registers
+    pub fn with_registers(program: Vec<Inst>, register_count: usize) -> Self {
         Machine {
-            registers: [Word::Free; 8],
+            registers: vec![Word::Free; register_count],
             data: Vec::new(),
 
             stack: Stack::new(),
@@ -77,22 +163,168 @@ impl Machine {
 
             files: HashMap::new(),
             file_id_counter: 0,
+
+            input: Box::new(BufReader::new(std::io::stdin())),
+            output: Box::new(std::io::stdout()),
+            fs: Box::new(HostFs),
+            sandbox: Sandbox::default(),
+
+            natives: Vec::new(),
+
+            args: Vec::new(),
+            env_allowlist: HashSet::new(),
             
+            fuel: None,
+            costs: HashMap::new(),
+            deadline: None,
+            deadline_countdown: 0,
+            steps: 0,
+
+            io_mode: IoMode::Live,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            debug_info: None,
+            fault: None,
+            journal: None,
+            reads: RefCell::new(None),
+            heap_segments: RefCell::new(None),
+            watchpoints: Vec::new(),
+            watch_log: Vec::new(),
+            watch_pause: None,
+            history: None,
+
             ip: 0,
             program,
-            exit: false,
+            exit: None,
+            breakpoints: HashSet::new(),
             halt: false,
             debug: false,
+            verified: false,
+        }
+    }
+
+    /// Run program without verifying it first
+    pub fn skip_verify(&mut self) {
+        self.verified = true;
+    }
+
+    /// Replace stdin used by `Read`
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Box::new(input);
+    }
+
+    /// Replace stdout used by `Write`
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Replace filesystem used by `Open`
+    pub fn set_fs(&mut self, fs: impl FileSystem + 'static) {
+        self.fs = Box::new(fs);
+    }
+
+    /// Restrict what `Open` may access
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.sandbox = sandbox;
+    }
+}
+
+/// Assemble a source file, exiting with the error position on failure
+fn assemble_file(path: &str) -> bytecode::Module {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
         }
+    };
+
+    match asm::assemble(&source, path) {
+        Ok(module) => module,
+        Err(err) => {
+            eprintln!("{}:{}:{}: {}", err.loc.file, err.loc.line, err.loc.col, err.message);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Assemble a `.asm` file or load a `.cvm` one
+fn load_module(path: &str) -> Result<bytecode::Module, Error> {
+    if path.ends_with(".asm") {
+        return Ok(assemble_file(path));
     }
+
+    let file = std::fs::File::open(path).map_err(|_| Error::IO)?;
+    bytecode::Module::load(BufReader::new(file))
 }
 
 fn main() -> Result<(), Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // cvm46 --assemble in.asm out.cvm
+    if args.first().map(String::as_str) == Some("--assemble") {
+        let (Some(input), Some(output)) = (args.get(1), args.get(2)) else {
+            eprintln!("usage: cvm46 --assemble in.asm out.cvm");
+            std::process::exit(2);
+        };
+
+        let module = assemble_file(input);
+        let file = std::fs::File::create(output).map_err(|_| Error::IO)?;
+        return module.save(std::io::BufWriter::new(file));
+    }
+
+    // cvm46 --debug [--input file] program.asm|program.cvm [args...],
+    // commands on stdin. The program reads the input file, or nothing
+    if args.first().map(String::as_str) == Some("--debug") {
+        let (input, rest) = match args.get(1).map(String::as_str) {
+            Some("--input") => (args.get(2), args.get(3..).unwrap_or_default()),
+            _ => (None, &args[1..]),
+        };
+        let Some(path) = rest.first() else {
+            eprintln!("usage: cvm46 --debug [--input file] program.asm|program.cvm [args...]");
+            std::process::exit(2);
+        };
+
+        let mut machine = Machine::from_module(load_module(path)?);
+        machine.set_args(&rest[1..])?;
+        match input {
+            Some(input) => {
+                let file = std::fs::File::open(input).map_err(|_| Error::IO)?;
+                machine.set_input(BufReader::new(file));
+            }
+            None => machine.set_input(std::io::empty()),
+        }
+        let mut debugger = debugger::Debugger::new(machine);
+        return debugger.repl(std::io::stdin().lock(), std::io::stdout());
+    }
+
+    // cvm46 program.asm|program.cvm [args...]
+    if let Some(path) = args.first().filter(|path| path.ends_with(".asm") || path.ends_with(".cvm")) {
+        let mut machine = Machine::from_module(load_module(path)?);
+        machine.set_args(&args[1..])?;
+        match machine.run() {
+            Ok(run::Outcome::Exited(Word::Int(code))) => std::process::exit(code as i32),
+            Ok(_) => return Ok(()),
+            Err(Error::VerifyFailed) => {
+                for err in machine.verify().err().unwrap_or_default() {
+                    eprintln!("error: {:?} at {}", err.kind, machine.describe_location(err.index));
+                }
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("error: {}", machine.describe_error(err));
+                std::process::exit(1);
+            }
+        }
+    }
+
     let program = vec![
         Inst::new(InstType::Pushs, [Word::Ptr(Pointer::Data(0)), Word::None]),
     ];
 
     let mut machine = Machine::new(program);
+    machine.set_args(&args)?;
     machine.data.push(vec![
         Word::Char('h'),
         Word::Char('e'),
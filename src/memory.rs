@@ -1,45 +1,123 @@
 use super::*;
 
+/// Max words on the heap
+pub const MAX_HEAP: usize = 1 << 24;
+
+/// Order between Words of different type, for memcmp
+fn word_rank(word: &Word) -> u8 {
+    match word {
+        Word::Int(_) => 0,
+        Word::Float(_) => 1,
+        Word::Double(_) => 2,
+        Word::Ptr(_) => 3,
+        Word::Char(_) => 4,
+        Word::Free => 5,
+        Word::None => 6,
+    }
+}
+
+/// Pointer to index in the same region as ptr
+fn same_region(ptr: Pointer, index: usize) -> Pointer {
+    match ptr {
+        Pointer::Stack(_) => Pointer::Stack(index),
+        Pointer::Register(_) => Pointer::Register(index),
+        _ => Pointer::Heap(index),
+    }
+}
+
 impl Machine {
-    /// Free heap-allocated segment
+    /// Note that n words at ptr are about to be written, keeping their old
+    /// values in the journal while one is taken
+    pub fn touch(&mut self, ptr: Pointer, n: usize) {
+        if let Pointer::Heap(start) = ptr {
+            self.heap_written(start, n);
+        }
+
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+
+        let segment = match ptr {
+            Pointer::Heap(_) => &self.heap,
+            Pointer::Stack(_) => &self.stack.stack,
+            Pointer::Register(_) => &self.registers,
+            _ => return,
+        };
+
+        let start = ptr.as_usize();
+        for index in start..start.saturating_add(n) {
+            journal.push((same_region(ptr, index), segment.get(index).copied()));
+        }
+    }
+
+    /// Note that n words at ptr are read, while a read log is taken
+    pub fn peek(&self, ptr: Pointer, n: usize) {
+        if let Some(reads) = self.reads.borrow_mut().as_mut() {
+            reads.push((ptr, n));
+        }
+    }
+
+    /// Fail unless ptr points at the first element of its segment, so the
+    /// word before it is the length header and not an element
+    fn segment_start(&self, ptr: Pointer) -> Result<(), Error> {
+        let start = ptr.as_usize();
+        let bounds = match ptr {
+            Pointer::Heap(_) => self.heap_segment(start).ok(),
+            Pointer::Stack(_) => self.stack.segment(start),
+            _ => return Err(Error::InvalidPointer),
+        };
+
+        match bounds {
+            Some((seg_start, _)) if seg_start == start => Ok(()),
+            _ => Err(Error::InvalidPointer),
+        }
+    }
+
+    /// Free heap-allocated segment. ptr is the pointer malloc returned, to
+    /// the first element, and the length header is read at ptr - 1
     pub fn free(&mut self, ptr: Pointer) -> Result<(), Error> {
+        self.segment_start(ptr)?;
         let segment = match ptr {
-            Pointer::Stack(_) => &mut self.stack.stack,
-            Pointer::Heap(_) => &mut self.heap,
+            Pointer::Stack(_) => &self.stack.stack,
+            Pointer::Heap(_) => &self.heap,
             _ => return Err(Error::InvalidPointer),
         };
 
-        let ptr = ptr.as_usize();
-        if ptr >= segment.len() {
+        // Length is stored in front of the first element
+        let start = ptr.as_usize();
+        if start < 1 || start > segment.len() {
             return Err(Error::SegmentationFault);
         }
 
-        if let Word::Int(len) = segment[ptr] {
-            let len = len as usize;
-            let end = ptr + len;
-            let start = ptr + 1;
+        let len_ptr = start - 1;
+        let end = match segment[len_ptr] {
+            Word::Int(len) => {
+                let end = start.saturating_add(len as usize);
 
-            // Checking if the slice pointers are valid
-            if end > segment.len() || start > segment.len() || start >= end {
-                return Err(Error::SegmentationFault);
-            }
-
-            // Freeing the segment by setting all elements to Word::Free
-            for i in start..end {
-                segment[i] = Word::Free;
+                // Checking if the slice pointers are valid
+                if len < 0 || end > segment.len() {
+                    return Err(Error::SegmentationFault);
+                }
+                end
             }
+            _ => return Err(Error::TypeMismatch),
+        };
 
-            segment[ptr] = Word::Free;
-        } 
-        else {
-            return Err(Error::TypeMismatch);
-        }
+        self.touch(same_region(ptr, len_ptr), end - len_ptr);
+        let segment = match ptr {
+            Pointer::Stack(_) => &mut self.stack.stack,
+            _ => &mut self.heap,
+        };
 
+        // Freeing the segment and its length by setting them to Word::Free
+        segment[len_ptr..end].fill(Word::Free);
         Ok(())
     }
 
 
-    // Allocates Word's on the Heap
+    /// Allocates len words on the Heap behind a length header, all Int(0).
+    /// The first run of free words that fits the header too is reused,
+    /// shorter runs are skipped, otherwise the heap grows
     pub fn malloc(&mut self, len: usize) -> Result<Pointer, Error> {
         let mut start_index = None;
         let mut segment_length = 0;
@@ -53,7 +131,7 @@ impl Machine {
                     segment_length += 1;
 
                     // +1 to fit the length
-                    if segment_length >= len + 1 {
+                    if segment_length > len {
                         break;
                     }
                 }
@@ -66,12 +144,23 @@ impl Machine {
 
         // Returns Pointer to suitable segment
         if let Some(start) = start_index {
-            self.heap[start] = Word::Int(len as i64);
-            return Ok(Pointer::Heap(start + 1)); 
+            if segment_length > len {
+                self.touch(Pointer::Heap(start), len + 1);
+                self.heap[start] = Word::Int(len as i64);
+                for word in &mut self.heap[start + 1..start + 1 + len] {
+                    *word = Word::Int(0);
+                }
+                return Ok(Pointer::Heap(start + 1)); 
+            }
         }
 
         // Expands heap if no suitable segments already
+        if len >= MAX_HEAP - self.heap.len() {
+            return Err(Error::OutOfMemory);
+        }
+
         let start_index = self.heap.len();
+        self.touch(Pointer::Heap(start_index), len + 1);
         self.heap.push(Word::Int(len as i64));
         for _ in 0..len {
             self.heap.push(Word::Int(0));
@@ -81,11 +170,20 @@ impl Machine {
         Ok(Pointer::Heap(start_index + 1)) 
     }
 
+    /// Allocates segment on the Heap holding a copy of arr
+    pub fn alloc_segment(&mut self, arr: &[Word]) -> Result<Pointer, Error> {
+        let ptr = self.malloc(arr.len())?;
+        let start = ptr.as_usize();
+        self.touch(ptr, arr.len());
+        self.heap[start..start + arr.len()].copy_from_slice(arr);
+        Ok(ptr)
+    }
+
     /// Sets Element 
     pub fn setelem(&mut self, elem: Pointer, value: Word) -> Result<(), Error> {
         let segment = match elem {
-            Pointer::Heap(_) => &mut self.heap,
-            Pointer::Stack(_) => &mut self.stack.stack,
+            Pointer::Heap(_) => &self.heap,
+            Pointer::Stack(_) => &self.stack.stack,
             _ => return Err(Error::InvalidPointer),
         };
 
@@ -96,7 +194,11 @@ impl Machine {
             return Err(Error::SegmentationFault);
         }
         
-        segment[elem_ptr] = value;
+        self.touch(elem, 1);
+        match elem {
+            Pointer::Heap(_) => self.heap[elem_ptr] = value,
+            _ => self.stack.stack[elem_ptr] = value,
+        }
 
         Ok(())
     }
@@ -106,32 +208,437 @@ impl Machine {
         let segment = match ptr {
             Pointer::Heap(_) => &self.heap,
             Pointer::Stack(_) => &self.stack.stack,
+
+            // Data segments are whole, without length header
+            Pointer::Data(index) => {
+                return self.data.get(index)
+                    .cloned()
+                    .ok_or(Error::SegmentationFault);
+            }
             _ => return Err(Error::InvalidPointer),
         };
 
+        // Length is stored in front of the first element
+        let region = ptr;
         let ptr = ptr.as_usize();
-        let len_ptr = ptr - 1;
-
-        if ptr >= segment.len() {
+        if ptr < 1 || ptr > segment.len() {
             return Err(Error::SegmentationFault);
         }
-        
-        if len_ptr >= segment.len() {
-            return Err(Error::SegmentationFault);
-        }
-        
-        if let Word::Int(len) = segment[len_ptr] {
-            let len = len as usize;
 
+        let len_ptr = ptr - 1;
+        if let Word::Int(len) = segment[len_ptr] {
             let start = ptr;
-            let end = start + len;
+            let end = start.saturating_add(len as usize);
+
+            if len < 0 || end > segment.len() {
+                return Err(Error::SegmentationFault);
+            }
+
+            self.peek(same_region(region, len_ptr), len as usize + 1);
             let arr_slice = &segment[start..end];
             let arr = arr_slice.to_vec();
             
             Ok(arr)
         } 
         else {
+            Err(Error::InvalidPointer)
+        }
+    }
+
+    /// Drop the parsed heap segments unless n words written at start stay
+    /// inside one of them, so only writes that may move a header reparse
+    fn heap_written(&self, start: usize, n: usize) {
+        let mut segments = self.heap_segments.borrow_mut();
+        let inside = segments.as_ref().is_some_and(|segments| {
+            let index = segments.partition_point(|(seg_start, _)| *seg_start <= start);
+            index > 0 && start.saturating_add(n) <= segments[index - 1].1
+        });
+
+        if !inside {
+            *segments = None;
+        }
+    }
+
+    /// Forget the parsed heap segments after the heap was replaced
+    pub fn heap_changed(&mut self) {
+        *self.heap_segments.get_mut() = None;
+    }
+
+    /// Bounds [start, end) of the heap segment ptr points into or one past.
+    /// The headers are parsed from the start of the heap once and kept
+    /// until a write may have moved them
+    pub fn heap_segment(&self, ptr: usize) -> Result<(usize, usize), Error> {
+        let mut cache = self.heap_segments.borrow_mut();
+        let segments = cache.get_or_insert_with(|| {
+            let mut segments = Vec::new();
+            let mut index = 0;
+            while index < self.heap.len() {
+                match self.heap[index] {
+                    Word::Int(len) if len >= 0 => {
+                        let start = index + 1;
+                        let end = start.saturating_add(len as usize).min(self.heap.len());
+                        segments.push((start, end));
+                        index = end.max(start);
+                    }
+                    _ => index += 1,
+                }
+            }
+            segments
+        });
+
+        let index = segments.partition_point(|(start, _)| *start <= ptr);
+        match index.checked_sub(1).map(|index| segments[index]) {
+            Some((start, end)) if ptr <= end => Ok((start, end)),
+            _ => Err(Error::SegmentationFault),
+        }
+    }
+
+    /// Bounds [start, end) of the stack segment ptr points into or one past,
+    /// a slot outside any segment pushed by Pushs or Read is one word
+    pub fn stack_segment(&self, ptr: usize) -> Result<(usize, usize), Error> {
+        match self.stack.segment(ptr) {
+            Some(bounds) => Ok(bounds),
+            None if ptr < self.stack.sp => Ok((ptr, ptr + 1)),
+            None => Err(Error::SegmentationFault),
+        }
+    }
+
+    /// Check that n words starting at ptr stay inside one segment
+    pub fn check_range(&self, ptr: Pointer, n: usize) -> Result<(), Error> {
+        let start = ptr.as_usize();
+        let end = start.checked_add(n).ok_or(Error::SegmentationFault)?;
+
+        let (seg_start, seg_end) = match ptr {
+            Pointer::Heap(_) if n == 0 => return Ok(()),
+            _ => self.segment_bounds(ptr)?,
+        };
+
+        if start < seg_start || end > seg_end {
+            return Err(Error::SegmentationFault);
+        }
+
+        Ok(())
+    }
+
+    /// Copy n words, source and destination may overlap
+    pub fn memmove(&mut self, dst: Pointer, src: Pointer, n: usize) -> Result<(), Error> {
+        self.check_range(src, n)?;
+        self.check_range(dst, n)?;
+
+        let src_start = src.as_usize();
+        self.peek(src, n);
+        let words: Vec<Word> = match src {
+            Pointer::Heap(_) => self.heap[src_start..src_start + n].to_vec(),
+            _ => self.stack.stack[src_start..src_start + n].to_vec(),
+        };
+
+        let dst_start = dst.as_usize();
+        self.touch(dst, n);
+        match dst {
+            Pointer::Heap(_) => self.heap[dst_start..dst_start + n].copy_from_slice(&words),
+            _ => self.stack.stack[dst_start..dst_start + n].copy_from_slice(&words),
+        }
+
+        Ok(())
+    }
+
+    /// Set n words to value. Word::Free is refused, it would forge a free
+    /// run that malloc hands out again
+    pub fn memset(&mut self, dst: Pointer, value: Word, n: usize) -> Result<(), Error> {
+        if value == Word::Free {
+            return Err(Error::IllegalOperandType);
+        }
+        self.check_range(dst, n)?;
+
+        let start = dst.as_usize();
+        self.touch(dst, n);
+        match dst {
+            Pointer::Heap(_) => self.heap[start..start + n].fill(value),
+            _ => self.stack.stack[start..start + n].fill(value),
+        }
+
+        Ok(())
+    }
+
+    /// Compare n words, returns -1, 0 or 1 at the first difference.
+    /// Words of different type compare by their type
+    pub fn memcmp(&self, a: Pointer, b: Pointer, n: usize) -> Result<i64, Error> {
+        self.check_range(a, n)?;
+        self.check_range(b, n)?;
+
+        self.peek(a, n);
+        self.peek(b, n);
+        let region = |ptr: Pointer| match ptr {
+            Pointer::Heap(p) => &self.heap[p..p + n],
+            _ => &self.stack.stack[ptr.as_usize()..ptr.as_usize() + n],
+        };
+
+        for (x, y) in region(a).iter().zip(region(b)) {
+            let ord = match (x, y) {
+                (Word::Int(x), Word::Int(y)) => x.partial_cmp(y),
+                (Word::Float(x), Word::Float(y)) => x.partial_cmp(y),
+                (Word::Double(x), Word::Double(y)) => x.partial_cmp(y),
+                (Word::Char(x), Word::Char(y)) => x.partial_cmp(y),
+                _ if x == y => Some(std::cmp::Ordering::Equal),
+                _ => word_rank(x).partial_cmp(&word_rank(y)),
+            };
+
+            match ord {
+                Some(std::cmp::Ordering::Less) => return Ok(-1),
+                Some(std::cmp::Ordering::Greater) => return Ok(1),
+                Some(std::cmp::Ordering::Equal) => continue,
+                None => return Err(Error::TypeMismatch),
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// Resize heap segment returned by malloc, in place if possible,
+    /// otherwise the contents are moved. Returns pointer to the segment
+    pub fn realloc(&mut self, ptr: Pointer, len: usize) -> Result<Pointer, Error> {
+        let start = match ptr {
+            Pointer::Heap(start) if start >= 1 && start <= self.heap.len() => start,
+            Pointer::Heap(_) => return Err(Error::SegmentationFault),
+            _ => return Err(Error::InvalidPointer),
+        };
+        self.segment_start(ptr)?;
+
+        let old_len = match self.heap[start - 1] {
+            Word::Int(old_len) if old_len >= 0 => old_len as usize,
+            _ => return Err(Error::InvalidPointer),
+        };
+
+        if old_len > self.heap.len() - start {
+            return Err(Error::SegmentationFault);
+        }
+
+        // Shrink, the tail becomes free
+        if len <= old_len {
+            self.touch(Pointer::Heap(start + len), old_len - len);
+            self.touch(Pointer::Heap(start - 1), 1);
+            for word in &mut self.heap[start + len..start + old_len] {
+                *word = Word::Free;
+            }
+            self.heap[start - 1] = Word::Int(len as i64);
+            return Ok(ptr);
+        }
+
+        // Grow in place over free words or the end of the heap
+        let old_end = start + old_len;
+        if len >= MAX_HEAP - start {
+            return Err(Error::OutOfMemory);
+        }
+
+        let new_end = start + len;
+        let free_after = self.heap[old_end..]
+            .iter()
+            .take_while(|word| **word == Word::Free)
+            .count();
+
+        if old_end + free_after >= new_end || old_end + free_after == self.heap.len() {
+            self.touch(Pointer::Heap(old_end), new_end - old_end);
+            self.touch(Pointer::Heap(start - 1), 1);
+            if new_end > self.heap.len() {
+                self.hp += new_end - self.heap.len();
+                self.heap.resize(new_end, Word::Free);
+            }
+
+            self.heap[old_end..new_end].fill(Word::Int(0));
+            self.heap[start - 1] = Word::Int(len as i64);
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.malloc(len)?;
+        self.memmove(new_ptr, ptr, old_len)?;
+        self.free(ptr)?;
+        Ok(new_ptr)
+    }
+
+    /// Bounds [start, end) of the segment ptr points into, or one past
+    fn segment_bounds(&self, ptr: Pointer) -> Result<(usize, usize), Error> {
+        match ptr {
+            Pointer::Heap(index) => self.heap_segment(index),
+            Pointer::Stack(index) => self.stack_segment(index),
+            _ => Err(Error::InvalidPointer),
+        }
+    }
+
+    /// Pointer moved by delta words, may point one past the end of its segment
+    pub fn ptr_offset(&self, ptr: Pointer, delta: i64) -> Result<Pointer, Error> {
+        let (start, end) = self.segment_bounds(ptr)?;
+        let index = (ptr.as_usize() as i64)
+            .checked_add(delta)
+            .ok_or(Error::SegmentationFault)?;
+
+        if index < start as i64 || index > end as i64 {
+            return Err(Error::SegmentationFault);
+        }
+
+        Ok(match ptr {
+            Pointer::Heap(_) => Pointer::Heap(index as usize),
+            _ => Pointer::Stack(index as usize),
+        })
+    }
+
+    /// Distance in words between two pointers into the same segment
+    pub fn ptr_diff(&self, a: Pointer, b: Pointer) -> Result<i64, Error> {
+        let same_region = matches!(
+            (a, b),
+            (Pointer::Heap(_), Pointer::Heap(_)) | (Pointer::Stack(_), Pointer::Stack(_))
+        );
+
+        if !same_region || self.segment_bounds(a)? != self.segment_bounds(b)? {
             return Err(Error::InvalidPointer);
         }
+
+        Ok(a.as_usize() as i64 - b.as_usize() as i64)
+    }
+
+    /// Pointer to element index of the segment base points into
+    fn element(&self, base: Pointer, index: i64) -> Result<Pointer, Error> {
+        let elem = self.ptr_offset(base, index)?;
+        self.check_range(elem, 1)?;
+        Ok(elem)
+    }
+
+    /// Load element at base + index
+    pub fn load_idx(&self, base: Pointer, index: i64) -> Result<Word, Error> {
+        let elem = self.element(base, index)?;
+        self.peek(elem, 1);
+        Ok(match elem {
+            Pointer::Heap(i) => self.heap[i],
+            _ => self.stack.stack[elem.as_usize()],
+        })
+    }
+
+    /// Store value at base + index, not Word::Free, see memset
+    pub fn store_idx(&mut self, base: Pointer, index: i64, value: Word) -> Result<(), Error> {
+        if value == Word::Free {
+            return Err(Error::IllegalOperandType);
+        }
+        let elem = self.element(base, index)?;
+        self.touch(elem, 1);
+        match elem {
+            Pointer::Heap(i) => self.heap[i] = value,
+            _ => self.stack.stack[elem.as_usize()] = value,
+        }
+
+        Ok(())
+    }
+
+    /// Length header of the segment ptr points into
+    pub fn segment_len(&self, ptr: Pointer) -> Result<usize, Error> {
+        match ptr {
+            Pointer::Heap(_) => {
+                let (start, end) = self.segment_bounds(ptr)?;
+                Ok(end - start)
+            }
+            Pointer::Stack(index) => match self.stack.segment(index) {
+                Some((start, end)) => Ok(end - start),
+                None => Ok(self.read_arr(ptr)?.len()),
+            },
+            Pointer::Data(index) => self.data.get(index)
+                .map(|segment| segment.len())
+                .ok_or(Error::SegmentationFault),
+            _ => Ok(self.read_arr(ptr)?.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    /// Stack after the program exits, or the error with the index of the
+    /// failing instruction
+    fn run(source: &str) -> Result<Vec<Word>, (Error, Option<usize>)> {
+        let mut machine = Machine::from_module(assemble(source, "test.asm").unwrap());
+        machine.skip_verify();
+        match machine.run() {
+            Ok(crate::run::Outcome::Exited(_)) => Ok(machine.stack.stack),
+            Ok(outcome) => panic!("unexpected {:?}", outcome),
+            Err(err) => Err((err, machine.fault())),
+        }
+    }
+
+    #[test]
+    fn freed_run_is_reused_zeroed() {
+        let source = "  alloc 2\n  pushi 1\n  pushi 7\n  storeidx\n  free heap:1\n  alloc 2\n  dup\n  pushi 1\n  loadidx\n  exit 0\n";
+        assert_eq!(run(source).unwrap(), vec![Word::Ptr(Pointer::Heap(1)), Word::Int(0)]);
+    }
+
+    #[test]
+    fn short_free_run_is_skipped() {
+        let source = "  alloc 1\n  alloc 1\n  free heap:1\n  alloc 2\n  exit 0\n";
+        let stack = run(source).unwrap();
+        assert_eq!(stack.last(), Some(&Word::Ptr(Pointer::Heap(5))));
+    }
+
+    #[test]
+    fn free_takes_the_pointer_malloc_returned() {
+        let source = "  alloc 2\n  free heap:1\n  alloc 2\n  exit 0\n";
+        assert_eq!(run(source).unwrap(), vec![Word::Ptr(Pointer::Heap(1)), Word::Ptr(Pointer::Heap(1))]);
+
+        let source = "  alloc 2\n  free heap:0\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::InvalidPointer, Some(1))));
+    }
+
+    #[test]
+    fn interior_pointers_cannot_be_freed() {
+        let source = "  alloc 3\n  free heap:2\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::InvalidPointer, Some(1))));
+
+        let source = "  alloc 3\n  pushi 1\n  ptradd\n  pushi 1\n  realloc\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::InvalidPointer, Some(4))));
+
+        let source = "  pushs \"abc\"\n  free stack:2\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::InvalidPointer, Some(1))));
+
+        let source = "  pushs \"abc\"\n  free stack:1\n  exit 0\n";
+        assert!(run(source).is_ok());
+    }
+
+    #[test]
+    fn memset_cannot_forge_free_words() {
+        let source = "  alloc 3\n  pushr r0\n  pushi 3\n  memset\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::IllegalOperandType, Some(3))));
+
+        let source = "  alloc 3\n  pushi 0\n  pushr r0\n  storeidx\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::IllegalOperandType, Some(3))));
+    }
+
+    #[test]
+    fn stack_string_is_bounded_by_its_header() {
+        let source = "  pushs \"ab\"\n  pushi 1\n  loadidx\n  exit 0\n";
+        assert_eq!(run(source).unwrap().last(), Some(&Word::Char('b')));
+
+        let source = "  pushs \"ab\"\n  dup\n  pushi 2\n  loadidx\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::SegmentationFault, Some(3))));
+
+        let source = "  pushs \"ab\"\n  pushi 3\n  ptradd\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::SegmentationFault, Some(2))));
+
+        let source = "  pushs \"ab\"\n  pushi 2\n  pushc 'x'\n  storeidx\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::SegmentationFault, Some(3))));
+    }
+
+    #[test]
+    fn len_of_moved_stack_pointer() {
+        let source = "  pushs \"abc\"\n  pushi 1\n  ptradd\n  len _\n  exit 0\n";
+        assert_eq!(run(source).unwrap().last(), Some(&Word::Int(3)));
+    }
+
+    #[test]
+    fn len_of_empty_heap_segment() {
+        let source = "  alloc 0\n  len _\n  exit 0\n";
+        assert_eq!(run(source).unwrap().last(), Some(&Word::Int(0)));
+    }
+
+    #[test]
+    fn shrunk_segment_is_bounded_by_its_new_header() {
+        let source = "  alloc 2\n  dup\n  pushi 1\n  loadidx\n  pop\n  pushi 1\n  realloc\n  pushi 1\n  loadidx\n  exit 0\n";
+        assert_eq!(run(source), Err((Error::SegmentationFault, Some(8))));
     }
 }
@@ -0,0 +1,72 @@
+use super::*;
+use crate::utils::to_string;
+use std::rc::Rc;
+
+/// Host function callable from bytecode with `CallNative`
+pub type NativeFn = dyn Fn(&mut Machine, &[Word]) -> Result<Vec<Word>, Error>;
+
+impl Machine {
+    /// Register named native function, returns its index.
+    /// Registering an existing name replaces the function
+    pub fn register_native<F>(&mut self, name: &str, func: F) -> usize
+    where
+        F: Fn(&mut Machine, &[Word]) -> Result<Vec<Word>, Error> + 'static,
+    {
+        if let Some(index) = self.natives.iter().position(|(n, _)| n == name) {
+            self.natives[index].1 = Rc::new(func);
+            return index;
+        }
+
+        self.natives.push((name.to_string(), Rc::new(func)));
+        self.natives.len() - 1
+    }
+
+    /// Resolve `CallNative` names (char segments in data) to registry indices
+    pub fn link(&mut self) -> Result<(), Error> {
+        for i in 0..self.program.len() {
+            let inst = &self.program[i];
+            if !matches!(inst.inst_type, InstType::CallNative) {
+                continue;
+            }
+
+            if let Word::Ptr(Pointer::Data(data_ptr)) = inst.operand[0] {
+                let segment = self.data.get(data_ptr).ok_or(Error::InvalidPointer)?;
+                let name = to_string(segment)?;
+
+                let index = self.natives.iter()
+                    .position(|(n, _)| *n == name)
+                    .ok_or(Error::UnresolvedNative)?;
+
+                self.program[i].operand[0] = Word::Ptr(Pointer::Native(index));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pop argc arguments, call native function and push its results
+    pub fn call_native(&mut self, index: usize, argc: usize) -> Result<(), Error> {
+        let func = match self.natives.get(index) {
+            Some((_, func)) => Rc::clone(func),
+            None => return Err(Error::UnresolvedNative),
+        };
+
+        if self.stack.sp < argc {
+            return Err(Error::StackUnderflow);
+        }
+
+        // Arguments in the order they were pushed
+        let mut args = Vec::with_capacity(argc);
+        for _ in 0..argc {
+            args.push(self.stack.pop()?);
+        }
+        args.reverse();
+
+        let results: Vec<Word> = self.host(HostCall::Native, |m| func(m, &args))?;
+        for value in results {
+            self.stack.push(value)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use super::*;
+use std::fmt::Write as _;
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+
+/// Executions and time spent
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counter {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+/// Execution counts and times per instruction, instruction type and
+/// call stack. Functions are the targets of `Call`, named from debug info
+/// or by index
+#[derive(Debug, Default)]
+pub struct Profiler {
+    pub per_ip: Vec<Counter>,
+    pub per_type: HashMap<InstType, Counter>,
+    /// Self counts per call path of function entry indices
+    pub stacks: HashMap<Vec<usize>, Counter>,
+    calls: Vec<usize>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    fn record(&mut self, ip: usize, inst_type: InstType, time: Duration) {
+        if ip >= self.per_ip.len() {
+            self.per_ip.resize(ip + 1, Counter::default());
+        }
+        self.per_ip[ip].add(time);
+        self.per_type.entry(inst_type).or_default().add(time);
+
+        match self.stacks.get_mut(self.calls.as_slice()) {
+            Some(counter) => counter.add(time),
+            None => {
+                let mut counter = Counter::default();
+                counter.add(time);
+                self.stacks.insert(self.calls.clone(), counter);
+            }
+        }
+    }
+
+    /// Self counts per function, None is the code outside any call
+    pub fn per_function(&self) -> HashMap<Option<usize>, Counter> {
+        let mut functions: HashMap<Option<usize>, Counter> = HashMap::new();
+        for (path, counter) in &self.stacks {
+            let function = functions.entry(path.last().copied()).or_default();
+            function.count += counter.count;
+            function.time += counter.time;
+        }
+        functions
+    }
+
+    /// Flat report: functions, instruction types and instructions, most
+    /// time first
+    pub fn report(&self, debug_info: Option<&DebugInfo>) -> String {
+        let mut out = String::new();
+
+        let mut functions: Vec<_> = self.per_function().into_iter().collect();
+        functions.sort_by_key(|(_, counter)| Reverse(counter.time));
+        let _ = writeln!(out, "{:<16} {:>12} {:>14}", "function", "count", "time (ns)");
+        for (function, counter) in functions {
+            let _ = writeln!(out, "{:<16} {:>12} {:>14}", function_name(function, debug_info), counter.count, counter.time.as_nanos());
+        }
+
+        let mut types: Vec<_> = self.per_type.iter().collect();
+        types.sort_by_key(|(_, counter)| Reverse(counter.time));
+        let _ = writeln!(out, "\n{:<16} {:>12} {:>14}", "instruction", "count", "time (ns)");
+        for (inst_type, counter) in types {
+            let _ = writeln!(out, "{:<16} {:>12} {:>14}", format!("{:?}", inst_type), counter.count, counter.time.as_nanos());
+        }
+
+        let mut ips: Vec<_> = self.per_ip.iter().enumerate().filter(|(_, c)| c.count > 0).collect();
+        ips.sort_by_key(|(_, counter)| Reverse(counter.time));
+        let _ = writeln!(out, "\n{:<16} {:>12} {:>14}", "ip", "count", "time (ns)");
+        for (ip, counter) in ips {
+            let _ = write!(out, "{:<16} {:>12} {:>14}", ip, counter.count, counter.time.as_nanos());
+            if let Some(debug_info) = debug_info {
+                let _ = write!(out, "  {}", debug_info.describe(ip));
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// One `main;fn@3;fn@9 count` line per call path, the input format of
+    /// flame graph tools
+    pub fn collapsed(&self, debug_info: Option<&DebugInfo>) -> String {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(path, counter)| {
+                let names: Vec<String> = std::iter::once(function_name(None, debug_info))
+                    .chain(path.iter().map(|entry| function_name(Some(*entry), debug_info)))
+                    .collect();
+                format!("{} {}", names.join(";"), counter.count)
+            })
+            .collect();
+
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+}
+
+/// Name of the function entered at entry: the debug info function
+/// starting there, else a label there, else `fn@entry`
+fn function_name(entry: Option<usize>, debug_info: Option<&DebugInfo>) -> String {
+    let Some(entry) = entry else {
+        return "main".to_string();
+    };
+
+    let name = debug_info.and_then(|debug_info| {
+        debug_info.functions.iter()
+            .find(|function| function.start == entry)
+            .map(|function| function.name.as_str())
+            .or_else(|| debug_info.label_at(entry))
+    });
+
+    match name {
+        Some(name) => name.to_string(),
+        None => format!("fn@{}", entry),
+    }
+}
+
+impl Machine {
+    /// Profile the instructions executed from now on
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stop profiling and return what was collected
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Profiler report with names and source positions from debug info
+    pub fn profile_report(&self) -> Option<String> {
+        Some(self.profiler.as_ref()?.report(self.debug_info.as_ref()))
+    }
+
+    /// Execute inst, timing it if the profiler is on
+    pub fn profile_inst(&mut self, inst: &Inst) -> Result<(), Error> {
+        if self.profiler.is_none() {
+            return self.exec_inst(inst);
+        }
+
+        let ip = self.ip - 1;
+        let start = Instant::now();
+        let result = self.exec_inst(inst);
+        let time = start.elapsed();
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(ip, inst.inst_type, time);
+
+            // The call counts to the caller, what follows to the callee
+            match inst.inst_type {
+                InstType::Call if result.is_ok() => profiler.calls.push(self.ip),
+                InstType::Return if result.is_ok() => {
+                    profiler.calls.pop();
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(source: &str) -> Machine {
+        let mut machine = Machine::from_module(crate::asm::assemble(source, "test.asm").unwrap());
+        machine.enable_profiler();
+        machine.run().unwrap();
+        machine
+    }
+
+    const SOURCE: &str = "call f\ncall f\nhalt\n.func f\npushi 1\npop\nreturn\n.end\n";
+
+    #[test]
+    fn counts_per_instruction_and_type() {
+        let machine = profile(SOURCE);
+        let profiler = machine.profiler().unwrap();
+
+        let counts: Vec<u64> = profiler.per_ip.iter().map(|counter| counter.count).collect();
+        assert_eq!(counts, [1, 1, 1, 2, 2, 2]);
+        assert_eq!(profiler.per_type[&InstType::Call].count, 2);
+        assert_eq!(profiler.per_type[&InstType::Return].count, 2);
+        assert_eq!(profiler.per_type.get(&InstType::Jmp), None);
+    }
+
+    #[test]
+    fn calls_count_to_the_caller() {
+        let mut machine = profile(SOURCE);
+        let functions = machine.profiler().unwrap().per_function();
+        assert_eq!(functions[&None].count, 3);
+        assert_eq!(functions[&Some(3)].count, 6);
+
+        assert_eq!(machine.profiler().unwrap().collapsed(machine.debug_info.as_ref()), "main 3\nmain;f 6\n");
+        let profiler = machine.take_profiler().unwrap();
+        assert_eq!(profiler.collapsed(None), "main 3\nmain;fn@3 6\n");
+        assert!(machine.profile_report().is_none());
+    }
+
+    #[test]
+    fn report_lists_functions_types_and_instructions() {
+        let machine = profile(SOURCE);
+        let report = machine.profile_report().unwrap();
+
+        assert!(report.lines().any(|line| line.starts_with("f ") && line.contains(" 6 ")), "{}", report);
+        assert!(report.lines().any(|line| line.starts_with("Pushi ") && line.contains(" 2 ")), "{}", report);
+        assert!(report.lines().any(|line| line.starts_with("3 ") && line.ends_with("test.asm:5:1 in f")), "{}", report);
+    }
+}
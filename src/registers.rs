@@ -0,0 +1,164 @@
+use super::*;
+
+impl Machine {
+    /// Register index from a register operand
+    pub fn reg_index(&self, operand: Word) -> Result<usize, Error> {
+        match operand {
+            Word::Ptr(ptr) if ptr.as_usize() < self.registers.len() => Ok(ptr.as_usize()),
+            _ => Err(Error::IllegalInst),
+        }
+    }
+
+    /// Value of a register, noted in the read log
+    pub fn reg(&self, reg_index: usize) -> Word {
+        self.peek(Pointer::Register(reg_index), 1);
+        self.registers[reg_index]
+    }
+
+    /// Set a register, keeping its old value in the journal
+    pub fn set_reg(&mut self, reg_index: usize, value: Word) {
+        self.touch(Pointer::Register(reg_index), 1);
+        self.registers[reg_index] = value;
+    }
+
+    /// Load word the register points to
+    pub fn load_through(&self, reg_index: usize) -> Result<Word, Error> {
+        match self.reg(reg_index) {
+            Word::Ptr(ptr) => self.load_idx(ptr, 0),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    /// Store word where the register points to
+    pub fn store_through(&mut self, reg_index: usize, value: Word) -> Result<(), Error> {
+        match self.reg(reg_index) {
+            Word::Ptr(ptr) => self.store_idx(ptr, 0, value),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    /// rA = rA op rB
+    pub fn register_op<F>(&mut self, a: usize, b: usize, op: F) -> Result<(), Error>
+    where
+        F: Fn(Word, Word) -> Result<Word, Error>,
+    {
+        let value = arith::promote(op, self.reg(a), self.reg(b))?;
+        self.set_reg(a, value);
+        Ok(())
+    }
+
+    /// Push all registers, r0 first
+    pub fn push_all(&mut self) -> Result<(), Error> {
+        for i in 0..self.registers.len() {
+            self.stack.push(self.reg(i))?;
+        }
+
+        Ok(())
+    }
+
+    /// Pop all registers pushed by push_all
+    pub fn pop_all(&mut self) -> Result<(), Error> {
+        if self.stack.sp < self.registers.len() {
+            return Err(Error::StackUnderflow);
+        }
+
+        for i in (0..self.registers.len()).rev() {
+            let value = self.stack.pop()?;
+            self.set_reg(i, value);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::Outcome;
+
+    fn run(source: &str) -> (Result<Outcome, Error>, Machine) {
+        let mut machine = Machine::from_module(crate::asm::assemble(source, "test.asm").unwrap());
+        machine.skip_verify();
+        (machine.run(), machine)
+    }
+
+    #[test]
+    fn movr_copies_between_registers() {
+        let (result, machine) = run("mov r1, 'x'\nmovr r0, r1\n");
+        assert_eq!(result, Ok(Outcome::EndOfProgram));
+        assert_eq!(machine.registers[..2], [Word::Char('x'), Word::Char('x')]);
+    }
+
+    #[test]
+    fn load_and_store_through_heap_pointers() {
+        let (result, machine) = run("alloc 2\npopr r0\nmov r1, 7\nstore r0, r1\nload r2, r0\n");
+        assert_eq!(result, Ok(Outcome::EndOfProgram));
+        assert_eq!(machine.registers[2], Word::Int(7));
+        assert_eq!(machine.heap[1], Word::Int(7));
+    }
+
+    #[test]
+    fn load_and_store_through_stack_pointers() {
+        let (result, machine) = run("pushs \"ab\"\npopr r0\nload r1, r0\nmov r2, 'z'\nstore r0, r2\nload r3, r0\n");
+        assert_eq!(result, Ok(Outcome::EndOfProgram));
+        assert_eq!(machine.registers[1], Word::Char('a'));
+        assert_eq!(machine.registers[3], Word::Char('z'));
+        assert_eq!(machine.stack.stack[1..3], [Word::Char('z'), Word::Char('b')]);
+    }
+
+    #[test]
+    fn other_pointers_cannot_be_loaded_or_stored() {
+        for ptr in ["data:0", "file:0", "r3"] {
+            let (result, _) = run(&format!("pushs \"a\"\nmov r0, {}\nload r1, r0\n", ptr));
+            assert_eq!(result, Err(Error::InvalidPointer), "load {}", ptr);
+
+            let (result, _) = run(&format!("pushs \"a\"\nmov r0, {}\nmov r1, 1\nstore r0, r1\n", ptr));
+            assert_eq!(result, Err(Error::InvalidPointer), "store {}", ptr);
+        }
+
+        let (result, _) = run("mov r0, 1\nload r1, r0\n");
+        assert_eq!(result, Err(Error::TypeMismatch));
+
+        // One past the end of the segment
+        let (result, _) = run("alloc 1\npushi 1\nptradd\npopr r0\nload r1, r0\n");
+        assert_eq!(result, Err(Error::SegmentationFault));
+    }
+
+    #[test]
+    fn register_arithmetic() {
+        let (result, machine) = run("mov r0, 6\nmov r1, 4\naddr r0, r1\nmulr r0, r1\nsubr r0, r1\n");
+        assert_eq!(result, Ok(Outcome::EndOfProgram));
+        assert_eq!(machine.registers[0], Word::Int(36));
+
+        let (result, machine) = run("mov r0, 1\nmov r1, 0\ndivr r0, r1\n");
+        assert_eq!(result, Err(Error::DivByZero));
+        assert_eq!(machine.registers[0], Word::Int(1));
+    }
+
+    #[test]
+    fn register_count_is_configurable() {
+        let module = crate::asm::assemble("mov r2, 1\n", "test.asm").unwrap();
+        let mut machine = Machine::with_registers(module.program.clone(), 3);
+        assert_eq!(machine.verify(), Ok(()));
+        assert_eq!(machine.run(), Ok(Outcome::EndOfProgram));
+        assert_eq!(machine.registers, [Word::Free, Word::Free, Word::Int(1)]);
+
+        let mut machine = Machine::with_registers(module.program, 2);
+        assert!(machine.verify().is_err());
+        machine.skip_verify();
+        assert_eq!(machine.run(), Err(Error::IllegalInst));
+    }
+
+    #[test]
+    fn pushall_and_popall_preserve_registers_across_calls() {
+        let source = "mov r0, 1\nmov r7, 'a'\ncall f\nhalt\nf: pushall\nmov r0, 2\nmov r7, 'b'\npopall\nreturn\n";
+        let (result, machine) = run(source);
+        assert_eq!(result, Ok(Outcome::Halted));
+        assert_eq!(machine.registers[0], Word::Int(1));
+        assert_eq!(machine.registers[7], Word::Char('a'));
+        assert_eq!(machine.stack.sp, 0);
+
+        let (result, _) = run("pushi 1\npopall\n");
+        assert_eq!(result, Err(Error::StackUnderflow));
+    }
+}
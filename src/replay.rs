@@ -0,0 +1,505 @@
+use super::*;
+use crate::bytecode::{Decoder, Encoder};
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"CVM46LOG";
+const VERSION: u64 = 1;
+
+/// Host call whose result enters the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HostCall {
+    Read = 0,
+    Token = 1,
+    Char = 2,
+    ReadAll = 3,
+    Eof = 4,
+    Open = 5,
+    Close = 6,
+    ReadFile = 7,
+    WriteFile = 8,
+    Seek = 9,
+    Tell = 10,
+    FileSize = 11,
+    Exists = 12,
+    Remove = 13,
+    Rename = 14,
+    Mkdir = 15,
+    ListDir = 16,
+    GetEnv = 17,
+    Native = 18,
+}
+
+impl HostCall {
+    /// Every host call, indexed by its discriminant
+    pub const ALL: &'static [HostCall] = {
+        use HostCall::*;
+        &[
+            Read, Token, Char, ReadAll, Eof, Open, Close, ReadFile, WriteFile, Seek, Tell,
+            FileSize, Exists, Remove, Rename, Mkdir, ListDir, GetEnv, Native,
+        ]
+    };
+}
+
+/// Result of a host call as stored in the log
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Int(u64),
+    Char(char),
+    Str(String),
+    Strings(Vec<String>),
+    Ptr(Pointer),
+    Words(Vec<Word>),
+}
+
+/// Types host calls return
+pub trait HostValue: Sized {
+    fn to_value(&self) -> Value;
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+impl HostValue for () {
+    fn to_value(&self) -> Value {
+        Value::Unit
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        matches!(value, Value::Unit).then_some(())
+    }
+}
+
+impl HostValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Bool(*self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bool(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl HostValue for u64 {
+    fn to_value(&self) -> Value {
+        Value::Int(*self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Int(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl HostValue for char {
+    fn to_value(&self) -> Value {
+        Value::Char(*self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Char(c) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+impl HostValue for String {
+    fn to_value(&self) -> Value {
+        Value::Str(self.clone())
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Str(string) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+/// Unset environment variable is Unit
+impl HostValue for Option<String> {
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::Unit, String::to_value)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Unit => Some(None),
+            Value::Str(string) => Some(Some(string)),
+            _ => None,
+        }
+    }
+}
+
+impl HostValue for Vec<String> {
+    fn to_value(&self) -> Value {
+        Value::Strings(self.clone())
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Strings(strings) => Some(strings),
+            _ => None,
+        }
+    }
+}
+
+impl HostValue for Pointer {
+    fn to_value(&self) -> Value {
+        Value::Ptr(*self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Ptr(ptr) => Some(ptr),
+            _ => None,
+        }
+    }
+}
+
+impl HostValue for Vec<Word> {
+    fn to_value(&self) -> Value {
+        Value::Words(self.clone())
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Words(words) => Some(words),
+            _ => None,
+        }
+    }
+}
+
+/// Host call made while executing instruction number step
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub step: u64,
+    pub call: HostCall,
+    pub result: Result<Value, Error>,
+}
+
+/// Everything that entered the VM during a recorded run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IoLog {
+    pub events: Vec<Event>,
+}
+
+impl IoLog {
+    pub fn save(&self, out: impl Write) -> Result<(), Error> {
+        let mut enc = Encoder::new(out);
+        enc.bytes(MAGIC)?;
+        enc.u64(VERSION)?;
+
+        enc.usize(self.events.len())?;
+        for event in &self.events {
+            enc.u64(event.step)?;
+            enc.u8(event.call as u8)?;
+
+            let value = match &event.result {
+                Ok(value) => value,
+                Err(err) => {
+                    enc.bool(false)?;
+                    enc.u8(*err as u8)?;
+                    continue;
+                }
+            };
+
+            enc.bool(true)?;
+            match value {
+                Value::Unit => enc.u8(0)?,
+                Value::Bool(val) => {
+                    enc.u8(1)?;
+                    enc.bool(*val)?;
+                }
+                Value::Int(val) => {
+                    enc.u8(2)?;
+                    enc.u64(*val)?;
+                }
+                Value::Char(c) => {
+                    enc.u8(3)?;
+                    enc.word(Word::Char(*c))?;
+                }
+                Value::Str(string) => {
+                    enc.u8(4)?;
+                    enc.str(string)?;
+                }
+                Value::Strings(strings) => {
+                    enc.u8(5)?;
+                    enc.usize(strings.len())?;
+                    strings.iter().try_for_each(|string| enc.str(string))?;
+                }
+                Value::Ptr(ptr) => {
+                    enc.u8(6)?;
+                    enc.pointer(*ptr)?;
+                }
+                Value::Words(words) => {
+                    enc.u8(7)?;
+                    enc.words(words)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load(input: impl Read) -> Result<IoLog, Error> {
+        let mut dec = Decoder::new(input);
+        if &dec.bytes::<8>()? != MAGIC || dec.u64()? != VERSION {
+            return Err(Error::InvalidBytecode);
+        }
+
+        let mut events = Vec::new();
+        for _ in 0..dec.usize()? {
+            let step = dec.u64()?;
+            let call = *HostCall::ALL.get(dec.u8()? as usize).ok_or(Error::InvalidBytecode)?;
+
+            if !dec.bool()? {
+                let err = Error::from_u8(dec.u8()?).ok_or(Error::InvalidBytecode)?;
+                events.push(Event { step, call, result: Err(err) });
+                continue;
+            }
+
+            let value = match dec.u8()? {
+                0 => Value::Unit,
+                1 => Value::Bool(dec.bool()?),
+                2 => Value::Int(dec.u64()?),
+                3 => match dec.word()? {
+                    Word::Char(c) => Value::Char(c),
+                    _ => return Err(Error::InvalidBytecode),
+                },
+                4 => Value::Str(dec.str()?),
+                5 => {
+                    let mut strings = Vec::new();
+                    for _ in 0..dec.usize()? {
+                        strings.push(dec.str()?);
+                    }
+                    Value::Strings(strings)
+                }
+                6 => Value::Ptr(dec.pointer()?),
+                7 => Value::Words(dec.words()?),
+                _ => return Err(Error::InvalidBytecode),
+            };
+            events.push(Event { step, call, result: Ok(value) });
+        }
+
+        Ok(IoLog { events })
+    }
+}
+
+/// Equal values, floats compared by their bits so a NaN matches itself
+fn same_value(a: &Value, b: &Value) -> bool {
+    let same_word = |a: &Word, b: &Word| match (a, b) {
+        (Word::Float(a), Word::Float(b)) => a.to_bits() == b.to_bits(),
+        (Word::Double(a), Word::Double(b)) => a.to_bits() == b.to_bits(),
+        (a, b) => a == b,
+    };
+
+    match (a, b) {
+        (Value::Words(a), Value::Words(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_word(a, b)),
+        (a, b) => a == b,
+    }
+}
+
+/// Whether host calls are recorded or answered from a log
+#[derive(Default)]
+pub enum IoMode {
+    #[default]
+    Live,
+    Record(IoLog),
+    Replay { log: IoLog, next: usize },
+}
+
+impl Machine {
+    /// Log every host call result from now on
+    pub fn record(&mut self) {
+        self.io_mode = IoMode::Record(IoLog::default());
+    }
+
+    /// Stop recording and return the log
+    pub fn take_recording(&mut self) -> Option<IoLog> {
+        match std::mem::take(&mut self.io_mode) {
+            IoMode::Record(log) => Some(log),
+            _ => None,
+        }
+    }
+
+    /// Answer host calls from the log instead of the host. A call that does
+    /// not match the next event returns Error::ReplayDivergence
+    pub fn replay(&mut self, log: IoLog) {
+        self.io_mode = IoMode::Replay { log, next: 0 };
+    }
+
+    pub fn replaying(&self) -> bool {
+        matches!(self.io_mode, IoMode::Replay { .. })
+    }
+
+    /// Events of the replayed log not consumed yet
+    pub fn replay_remaining(&self) -> usize {
+        match &self.io_mode {
+            IoMode::Replay { log, next } => log.events.len() - next,
+            _ => 0,
+        }
+    }
+
+    /// Program ended, a replayed log has to be used up by now
+    pub fn replay_finished(&self) -> Result<(), Error> {
+        match self.replay_remaining() {
+            0 => Ok(()),
+            _ => Err(Error::ReplayDivergence),
+        }
+    }
+
+    /// Take the next replayed event, which has to be call at step
+    fn next_event(&mut self, step: u64, call: HostCall) -> Result<Result<Value, Error>, Error> {
+        let IoMode::Replay { log, next } = &mut self.io_mode else {
+            return Err(Error::ReplayDivergence);
+        };
+
+        let event = log.events.get(*next).ok_or(Error::ReplayDivergence)?;
+        if event.step != step || event.call != call {
+            return Err(Error::ReplayDivergence);
+        }
+
+        *next += 1;
+        Ok(event.result.clone())
+    }
+
+    /// Make a host call, recording its result or taking it from the log.
+    ///
+    /// Natives are run when replaying too, they get the machine and may
+    /// change it, and their results have to match the logged ones
+    pub fn host<T, F>(&mut self, call: HostCall, func: F) -> Result<T, Error>
+    where
+        T: HostValue,
+        F: FnOnce(&mut Machine) -> Result<T, Error>,
+    {
+        let step = self.steps;
+        self.reach_host();
+
+        if self.replaying() && call == HostCall::Native {
+            let result = func(self);
+            let logged = self.next_event(step, call)?;
+            let matches = match (&result, &logged) {
+                (Ok(value), Ok(expected)) => same_value(&value.to_value(), expected),
+                (Err(err), Err(expected)) => err == expected,
+                _ => false,
+            };
+
+            return match matches {
+                true => result,
+                false => Err(Error::ReplayDivergence),
+            };
+        }
+
+        if self.replaying() {
+            return match self.next_event(step, call)? {
+                Ok(value) => T::from_value(value).ok_or(Error::ReplayDivergence),
+                Err(err) => Err(err),
+            };
+        }
+
+        let result = func(self);
+        if let IoMode::Record(log) = &mut self.io_mode {
+            let logged = result.as_ref().map(T::to_value).map_err(|err| *err);
+            log.events.push(Event { step, call, result: logged });
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::run::Outcome;
+
+    fn machine(source: &str) -> Machine {
+        let mut machine = Machine::from_module(assemble(source, "test.asm").unwrap());
+        machine.skip_verify();
+        machine
+    }
+
+    /// Native that writes r1 and returns its argument plus one
+    fn register_bump(machine: &mut Machine) {
+        machine.register_native("bump", |m, args| {
+            m.registers[1] = Word::Int(42);
+            match args {
+                [Word::Int(n)] => Ok(vec![Word::Int(n + 1)]),
+                _ => Err(Error::IllegalInst),
+            }
+        });
+    }
+
+    #[test]
+    fn host_calls_match_their_index() {
+        for (index, call) in HostCall::ALL.iter().enumerate() {
+            assert_eq!(*call as usize, index, "{:?}", call);
+        }
+        assert_eq!(HostCall::ALL.len(), HostCall::Native as usize + 1);
+    }
+
+    const NATIVE: &str = "  pushi 1\n  callnative \"bump\", 1\n  exit 0\n";
+
+    #[test]
+    fn replayed_natives_change_the_machine() {
+        let mut recorded = machine(NATIVE);
+        register_bump(&mut recorded);
+        recorded.record();
+        recorded.run().unwrap();
+        assert_eq!(recorded.stack.stack, vec![Word::Int(2)]);
+        let log = recorded.take_recording().unwrap();
+
+        let mut replayed = machine(NATIVE);
+        register_bump(&mut replayed);
+        replayed.replay(log);
+        assert_eq!(replayed.run(), Ok(Outcome::Exited(Word::Int(0))));
+        assert_eq!(replayed.stack.stack, recorded.stack.stack);
+        assert_eq!(replayed.registers, recorded.registers);
+        assert_eq!(replayed.replay_finished(), Ok(()));
+    }
+
+    #[test]
+    fn native_with_another_result_diverges() {
+        let mut recorded = machine(NATIVE);
+        register_bump(&mut recorded);
+        recorded.record();
+        recorded.run().unwrap();
+        let log = recorded.take_recording().unwrap();
+
+        let mut replayed = machine(NATIVE);
+        replayed.register_native("bump", |_, _| Ok(vec![Word::Int(7)]));
+        replayed.replay(log);
+        assert_eq!(replayed.run(), Err(Error::ReplayDivergence));
+    }
+
+    #[test]
+    fn replay_keeps_the_file_table() {
+        let path = std::env::temp_dir().join(format!("cvm46-replay-{}", std::process::id()));
+        std::fs::write(&path, "x").unwrap();
+        let source = format!(
+            "  pushi 0\n  open \"{0}\"\n  pushi 0\n  open \"{0}\"\n  close file:0\n  exit 0\n",
+            path.display(),
+        );
+
+        let mut recorded = machine(&source);
+        recorded.record();
+        recorded.run().unwrap();
+        let log = recorded.take_recording().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut replayed = machine(&source);
+        replayed.replay(log);
+        assert_eq!(replayed.run(), Ok(Outcome::Exited(Word::Int(0))));
+
+        let mut ids: Vec<usize> = replayed.files.keys().copied().collect();
+        ids.sort();
+        assert_eq!(ids, vec![1]);
+        assert_eq!(replayed.file_id_counter, recorded.file_id_counter);
+    }
+}
@@ -0,0 +1,257 @@
+use super::*;
+use crate::run::Outcome;
+use crate::stack::Lowered;
+use crate::watch::{Access, WatchHit};
+use std::collections::VecDeque;
+
+/// What it takes to undo one executed instruction
+#[derive(Debug)]
+pub struct Undo {
+    /// Index of the instruction
+    ip: usize,
+    sp: usize,
+    sbp: usize,
+    /// Stack slots the instruction popped
+    lowered: Lowered,
+    /// Segments pushed on the stack before the instruction
+    segments: Vec<(usize, usize)>,
+    /// Old values of the words written through pointers and of registers
+    writes: Vec<(Pointer, Option<Word>)>,
+    heap_len: usize,
+    hp: usize,
+    exit: Option<Word>,
+    halt: bool,
+    /// Step count before the instruction was charged, so replayed host
+    /// calls and traces number the instruction the same when it runs again
+    steps: u64,
+}
+
+/// Undo records of the last executed instructions, oldest first
+#[derive(Debug, Default)]
+pub struct History {
+    undos: VecDeque<Undo>,
+    limit: usize,
+    /// The current instruction reached the host
+    host: bool,
+}
+
+impl Machine {
+    /// Record undo information for the next instructions executed, keeping
+    /// the last limit of them for step_back() and reverse_continue(). A
+    /// limit of 0 records nothing
+    pub fn record_history(&mut self, limit: usize) {
+        self.history = Some(History { undos: VecDeque::new(), limit, host: false });
+    }
+
+    /// Stop recording and drop the history
+    pub fn stop_history(&mut self) {
+        self.history = None;
+        self.stack.lowered = None;
+    }
+
+    /// Instructions that can be stepped back
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.undos.len())
+    }
+
+    /// Forget the recorded instructions, e.g. after the state was replaced
+    pub fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.undos.clear();
+        }
+    }
+
+    /// Note that the current instruction reads input, writes output or
+    /// makes another host call, none of which can be undone
+    pub fn reach_host(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.host = true;
+        }
+    }
+
+    /// State before an instruction, None if no history is recorded
+    pub fn undo_start(&mut self) -> Option<Undo> {
+        let history = self.history.as_mut().filter(|history| history.limit > 0)?;
+        history.host = false;
+
+        self.journal.get_or_insert_with(Vec::new).clear();
+        self.stack.lowered = Some(Lowered { low: self.stack.sp, words: Vec::new() });
+
+        Some(Undo {
+            ip: self.ip,
+            sp: self.stack.sp,
+            sbp: self.stack.sbp,
+            lowered: Lowered::default(),
+            segments: self.stack.segments.clone(),
+            writes: Vec::new(),
+            heap_len: self.heap.len(),
+            hp: self.hp,
+            exit: self.exit,
+            halt: self.halt,
+            // charge() already counted the instruction
+            steps: self.steps - 1,
+        })
+    }
+
+    /// Add the record of an executed instruction, writes is the journal
+    /// taken while it executed. An instruction that reached the host starts
+    /// the history over, stepping back past it would leave files, input and
+    /// output out of step with the machine
+    pub fn undo_end(&mut self, mut undo: Undo, writes: &[(Pointer, Option<Word>)]) {
+        undo.writes = writes.to_vec();
+        undo.lowered = self.stack.lowered.take().unwrap_or_default();
+
+        if let Some(history) = &mut self.history {
+            if history.host {
+                history.undos.clear();
+                return;
+            }
+            if history.undos.len() >= history.limit {
+                history.undos.pop_front();
+            }
+            history.undos.push_back(undo);
+        }
+    }
+
+    /// Undo the last recorded instruction and return its record
+    fn undo(&mut self) -> Option<Undo> {
+        let undo = self.history.as_mut()?.undos.pop_back()?;
+
+        // Newest first, so every word ends up with the value it had before
+        for (ptr, old) in undo.writes.iter().rev() {
+            let Some(old) = *old else {
+                continue;
+            };
+
+            let word = match ptr {
+                Pointer::Register(index) => self.registers.get_mut(*index),
+                Pointer::Heap(index) => self.heap.get_mut(*index),
+                Pointer::Stack(index) => self.stack.stack.get_mut(*index),
+                _ => None,
+            };
+            if let Some(word) = word {
+                *word = old;
+            }
+        }
+
+        // Popped slots go back over whatever was pushed in their place
+        self.stack.stack.truncate(undo.lowered.low);
+        self.stack.stack.extend_from_slice(&undo.lowered.words);
+        self.stack.stack.truncate(undo.sp);
+        self.stack.sp = undo.sp;
+        self.stack.sbp = undo.sbp;
+        self.stack.segments.clone_from(&undo.segments);
+
+        self.heap.truncate(undo.heap_len);
+        self.heap_changed();
+        self.hp = undo.hp;
+        self.ip = undo.ip;
+        self.exit = undo.exit;
+        self.halt = undo.halt;
+        self.steps = undo.steps;
+
+        Some(undo)
+    }
+
+    /// Undo the last executed instruction, false if there is no history
+    /// left, which is also the case right after a host call. Spent fuel is
+    /// not given back
+    pub fn step_back(&mut self) -> bool {
+        self.undo().is_some()
+    }
+
+    /// Step back until the previous write to a watched location, or until
+    /// ip is at a breakpoint. Stops before the writing instruction, with a
+    /// hit whose old and new are the values it wrote over and wrote
+    pub fn reverse_continue(&mut self) -> Outcome {
+        loop {
+            let watched: Vec<(Pointer, Option<Word>)> = self.watchpoints.iter()
+                .filter(|watchpoint| watchpoint.access != Access::Read)
+                .map(|watchpoint| (watchpoint.ptr, self.watched_value(watchpoint.ptr)))
+                .collect();
+
+            let Some(undo) = self.undo() else {
+                return Outcome::HistoryStart;
+            };
+
+            for (ptr, new) in watched {
+                let old = self.watched_value(ptr);
+                if old != new || undo.writes.iter().any(|(write, _)| *write == ptr) {
+                    return Outcome::Watchpoint(WatchHit { ip: self.ip, ptr, access: Access::Write, old, new });
+                }
+            }
+
+            if self.breakpoints.contains(&self.ip) {
+                return Outcome::Breakpoint;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::backend::SharedBuffer;
+
+    fn machine(source: &str) -> (Machine, SharedBuffer) {
+        let mut machine = Machine::from_module(assemble(source, "test.asm").unwrap());
+        let output = SharedBuffer::new();
+        machine.skip_verify();
+        machine.set_output(output.clone());
+        machine.record_history(100);
+        (machine, output)
+    }
+
+    #[test]
+    fn step_back_restores_the_stack() {
+        let (mut machine, _) = machine("  pushi 1\n  pushi 2\n  plus\n  exit 0\n");
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.stack.stack, vec![Word::Int(3)]);
+
+        while machine.step_back() {}
+        assert_eq!(machine.ip(), 0);
+        assert!(machine.stack.stack.is_empty());
+    }
+
+    #[test]
+    fn step_back_stops_after_host_calls() {
+        let (mut machine, output) = machine("  pushi 1\n  pushi 2\n  print\n  pushi 3\n  exit 0\n");
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+
+        assert!(machine.step_back());
+        assert!(!machine.step_back());
+        assert_eq!(machine.ip(), 3);
+        assert_eq!(machine.stack.stack, vec![Word::Int(1)]);
+        assert_eq!(output.contents_string(), "2");
+
+        machine.watch(Pointer::Stack(0), Access::Write, crate::watch::WatchAction::Pause).unwrap();
+        assert_eq!(machine.reverse_continue(), Outcome::HistoryStart);
+    }
+
+    #[test]
+    fn step_back_restores_the_step_count() {
+        let (mut machine, _) = machine("  pushi 1\n  pushi 2\n  plus\n  exit 0\n");
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.steps(), 2);
+
+        assert!(machine.step_back());
+        assert_eq!(machine.steps(), 1);
+        machine.step().unwrap();
+        assert_eq!(machine.steps(), 2);
+    }
+
+    #[test]
+    fn zero_limit_records_nothing() {
+        let (mut machine, _) = machine("  pushi 1\n  exit 0\n");
+        machine.record_history(0);
+        machine.step().unwrap();
+        assert_eq!(machine.history_len(), 0);
+        assert!(!machine.step_back());
+    }
+}
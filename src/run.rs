@@ -0,0 +1,125 @@
+use super::*;
+use crate::watch::WatchHit;
+use std::io::ErrorKind;
+
+/// Why run() returned, everything but Exited and EndOfProgram can be resumed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// `Exit` executed with this exit code
+    Exited(Word),
+    /// `Halt` executed, resume() continues after it
+    Halted,
+    /// ip ran past the last instruction
+    EndOfProgram,
+    /// Not enough fuel left for the next instruction
+    OutOfFuel,
+    /// Deadline passed before the next instruction
+    TimedOut,
+    /// ip reached a breakpoint, the instruction has not executed yet
+    Breakpoint,
+    /// Input instruction found no input ready, it runs again on resume().
+    /// Only an input that returns WouldBlock, i.e. a non-blocking reader set
+    /// with set_input, produces it, the default stdin blocks instead
+    WaitingForInput,
+    /// A pausing watchpoint triggered, the instruction has executed
+    Watchpoint(WatchHit),
+    /// reverse_continue() undid the oldest recorded instruction
+    HistoryStart,
+}
+
+impl Machine {
+    /// Run until the program stops or can not continue for now
+    pub fn run(&mut self) -> Result<Outcome, Error> {
+        self.run_from(false)
+    }
+
+    /// Continue from the saved ip after any outcome but Exited, stepping
+    /// over the breakpoint it stopped at
+    pub fn resume(&mut self) -> Result<Outcome, Error> {
+        self.halt = false;
+        self.run_from(true)
+    }
+
+    /// Stop before the instruction at index executes
+    pub fn set_breakpoint(&mut self, index: usize) {
+        self.breakpoints.insert(index);
+    }
+
+    pub fn clear_breakpoint(&mut self, index: usize) {
+        self.breakpoints.remove(&index);
+    }
+
+    /// Index of the next instruction
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Link natives and verify the program once, run() does this itself
+    pub fn prepare(&mut self) -> Result<(), Error> {
+        self.link()?;
+
+        if !self.verified {
+            if let Err(errors) = self.verify() {
+                self.fault = errors.first().map(|err| err.index);
+                return Err(Error::VerifyFailed);
+            }
+            self.verified = true;
+        }
+
+        Ok(())
+    }
+
+    fn run_from(&mut self, mut skip_breakpoint: bool) -> Result<Outcome, Error> {
+        self.prepare()?;
+
+        loop {
+            if let Some(code) = self.exit {
+                self.replay_finished()?;
+                return Ok(Outcome::Exited(code));
+            }
+            if self.halt {
+                return Ok(Outcome::Halted);
+            }
+            if self.ip >= self.program.len() {
+                self.replay_finished()?;
+                return Ok(Outcome::EndOfProgram);
+            }
+
+            if !skip_breakpoint && self.breakpoints.contains(&self.ip) {
+                return Ok(Outcome::Breakpoint);
+            }
+            skip_breakpoint = false;
+
+            if !self.input_ready(self.program[self.ip].inst_type)? {
+                return Ok(Outcome::WaitingForInput);
+            }
+
+            match self.step() {
+                Ok(_) => {
+                    if let Some(hit) = self.watch_pause.take() {
+                        return Ok(Outcome::Watchpoint(hit));
+                    }
+                }
+                Err(Error::OutOfFuel) => return Ok(Outcome::OutOfFuel),
+                Err(Error::Timeout) => return Ok(Outcome::TimedOut),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// False if the instruction reads input and the input would block. A
+    /// blocking reader such as the default stdin waits in fill_buf instead
+    fn input_ready(&mut self, inst_type: InstType) -> Result<bool, Error> {
+        use InstType::*;
+
+        if self.replaying() || !matches!(inst_type, Read | Scani | Scanf | Scand | Scanc | Reada | Eof) {
+            return Ok(true);
+        }
+
+        match self.input.fill_buf() {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(_) => Err(Error::IO),
+        }
+    }
+}
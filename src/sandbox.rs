@@ -0,0 +1,220 @@
+use crate::backend::{FileSystem, OpenMode};
+use crate::error::Error;
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Restrictions on what the program may do with the filesystem.
+/// The default allows everything, like an unsandboxed machine
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    /// Directory all paths are resolved under
+    pub root: Option<PathBuf>,
+    /// Allow `Read` opens, also `Exists` and `ListDir` as they reveal what is on disk
+    pub allow_read: bool,
+    pub allow_truncate: bool,
+    pub allow_append: bool,
+    /// Allow `Remove`, `Rename` and `Mkdir`
+    pub allow_modify: bool,
+    /// Max simultaneously open entries in `Machine::files`
+    pub max_open_files: Option<usize>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Sandbox {
+            root: None,
+            allow_read: true,
+            allow_truncate: true,
+            allow_append: true,
+            allow_modify: true,
+            max_open_files: None,
+        }
+    }
+}
+
+impl Sandbox {
+    /// Read-only sandbox rooted at `root`
+    pub fn read_only(root: impl Into<PathBuf>) -> Self {
+        Sandbox {
+            root: Some(root.into()),
+            allow_read: true,
+            allow_truncate: false,
+            allow_append: false,
+            allow_modify: false,
+            max_open_files: None,
+        }
+    }
+
+    pub fn allows(&self, mode: OpenMode) -> bool {
+        match mode {
+            OpenMode::Read => self.allow_read,
+            OpenMode::Truncate | OpenMode::Write => self.allow_truncate,
+            OpenMode::Append => self.allow_append,
+        }
+    }
+
+    /// Resolve path given by the program to a path under the root.
+    /// Rejects `..` escaping the root and symlinks pointing out of it
+    pub fn resolve(&self, fs: &dyn FileSystem, path: &str) -> Result<PathBuf, Error> {
+        let root = match &self.root {
+            Some(root) => root,
+            None => return Ok(PathBuf::from(path)),
+        };
+
+        // Lexically normalized, absolute paths are taken relative to root
+        let mut relative = PathBuf::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::ParentDir => {
+                    if !relative.pop() {
+                        return Err(Error::PermissionDenied);
+                    }
+                }
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            }
+        }
+
+        let canonical_root = fs.canonicalize(root).map_err(|_| Error::PermissionDenied)?;
+        let resolved = canonical_root.join(&relative);
+
+        // Canonicalize the longest existing ancestor. What canonicalize can
+        // not find is either missing or a dangling symlink, which opening
+        // would follow out of the root
+        let mut existing = resolved.as_path();
+        let mut rest = Vec::new();
+        let canonical = loop {
+            match fs.canonicalize(existing) {
+                Ok(canonical) => break canonical,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    if fs.is_symlink(existing) {
+                        return Err(Error::PermissionDenied);
+                    }
+                    rest.push(existing.file_name().ok_or(Error::PermissionDenied)?);
+                    existing = existing.parent().ok_or(Error::PermissionDenied)?;
+                }
+                Err(_) => return Err(Error::PermissionDenied),
+            }
+        };
+
+        if !canonical.starts_with(&canonical_root) {
+            return Err(Error::PermissionDenied);
+        }
+
+        Ok(rest.iter().rev().fold(canonical, |path, part| path.join(part)))
+    }
+
+    /// Resolve path to the directory entry itself: the parent is resolved,
+    /// a symlink at the end is not followed. For `Remove` and `Rename`,
+    /// which act on links rather than their targets
+    pub fn resolve_entry(&self, fs: &dyn FileSystem, path: &str) -> Result<PathBuf, Error> {
+        if self.root.is_none() {
+            return Ok(PathBuf::from(path));
+        }
+
+        let path = Path::new(path);
+        let name = path.file_name().ok_or(Error::PermissionDenied)?;
+        let parent = path.parent().and_then(Path::to_str).unwrap_or("");
+        Ok(self.resolve(fs, parent)?.join(name))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::backend::HostFs;
+    use crate::{Machine, Pointer};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    /// Fresh root directory and a directory outside of it
+    fn dirs(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("cvm46-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("root")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        (base.join("root"), base.join("outside"))
+    }
+
+    fn sandboxed(root: &Path) -> Machine {
+        let mut machine = Machine::new(Vec::new());
+        machine.set_sandbox(Sandbox { root: Some(root.to_path_buf()), ..Sandbox::default() });
+        machine
+    }
+
+    fn string(machine: &mut Machine, string: &str) -> Pointer {
+        machine.alloc_string(string).unwrap()
+    }
+
+    #[test]
+    fn dangling_symlink_out_of_root_is_denied() {
+        let (root, outside) = dirs("dangling");
+        symlink(outside.join("missing"), root.join("evil")).unwrap();
+
+        let sandbox = Sandbox { root: Some(root.clone()), ..Sandbox::default() };
+        assert_eq!(sandbox.resolve(&HostFs, "evil"), Err(Error::PermissionDenied));
+        assert_eq!(sandbox.resolve(&HostFs, "evil/below"), Err(Error::PermissionDenied));
+
+        let mut machine = sandboxed(&root);
+        let path = string(&mut machine, "evil");
+        assert_eq!(machine.open(path, 1), Err(Error::PermissionDenied));
+        assert_eq!(machine.open(path, 2), Err(Error::PermissionDenied));
+        assert!(!outside.join("missing").exists());
+    }
+
+    #[test]
+    fn missing_file_under_root_resolves() {
+        let (root, _) = dirs("missing");
+        let sandbox = Sandbox { root: Some(root.clone()), ..Sandbox::default() };
+        let canonical = fs::canonicalize(&root).unwrap();
+        assert_eq!(sandbox.resolve(&HostFs, "new/file"), Ok(canonical.join("new/file")));
+    }
+
+    #[test]
+    fn remove_symlink_removes_the_link() {
+        let (root, _) = dirs("remove");
+        fs::write(root.join("target"), "keep").unwrap();
+        symlink(root.join("target"), root.join("link")).unwrap();
+
+        let mut machine = sandboxed(&root);
+        let path = string(&mut machine, "link");
+        machine.remove(path).unwrap();
+
+        assert!(!root.join("link").is_symlink());
+        assert_eq!(fs::read_to_string(root.join("target")).unwrap(), "keep");
+    }
+
+    #[test]
+    fn read_file_leaves_the_cursor_after_the_line() {
+        let (root, _) = dirs("readline");
+        let long = "x".repeat(300);
+        fs::write(root.join("lines"), format!("{}\nab\nlast", long)).unwrap();
+
+        let mut machine = sandboxed(&root);
+        let path = string(&mut machine, "lines");
+        let file = machine.open(path, 0).unwrap();
+
+        let line = machine.read_file(file).unwrap();
+        assert_eq!(machine.read_string(line).unwrap(), long);
+        assert_eq!(machine.tell(file), Ok(301));
+        let line = machine.read_file(file).unwrap();
+        assert_eq!(machine.read_string(line).unwrap(), "ab");
+        let line = machine.read_file(file).unwrap();
+        assert_eq!(machine.read_string(line).unwrap(), "last");
+        assert_eq!(machine.tell(file), Ok(308));
+    }
+
+    #[test]
+    fn exists_needs_allow_read() {
+        let (root, _) = dirs("exists");
+        fs::write(root.join("file"), "").unwrap();
+
+        let mut machine = sandboxed(&root);
+        let path = string(&mut machine, "file");
+        assert_eq!(machine.exists(path), Ok(true));
+
+        machine.set_sandbox(Sandbox { root: Some(root.clone()), allow_read: false, ..Sandbox::default() });
+        assert_eq!(machine.exists(path), Err(Error::PermissionDenied));
+    }
+}
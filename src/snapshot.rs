@@ -0,0 +1,309 @@
+use super::*;
+use crate::backend::{io_error, OpenMode};
+use crate::bytecode::{Decoder, Encoder};
+use crate::memory::MAX_HEAP;
+use crate::stack::MAX_STACK;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"CVM46SNP";
+const VERSION: u64 = 2;
+
+/// Open file as recorded in a snapshot
+struct FileRecord {
+    id: usize,
+    path: String,
+    mode: OpenMode,
+    offset: u64,
+}
+
+fn mode_tag(mode: OpenMode) -> u8 {
+    match mode {
+        OpenMode::Read => 0,
+        OpenMode::Truncate => 1,
+        OpenMode::Append => 2,
+        OpenMode::Write => 3,
+    }
+}
+
+fn mode_from_tag(tag: u8) -> Result<OpenMode, Error> {
+    match tag {
+        0 => Ok(OpenMode::Read),
+        1 => Ok(OpenMode::Truncate),
+        2 => Ok(OpenMode::Append),
+        3 => Ok(OpenMode::Write),
+        _ => Err(Error::InvalidBytecode),
+    }
+}
+
+/// Point native pointers at the natives of the same name in the new machine
+fn remap_native(word: &mut Word, table: &[Option<usize>]) -> Result<(), Error> {
+    if let Word::Ptr(Pointer::Native(index)) = word {
+        *index = table.get(*index).copied().flatten().ok_or(Error::UnresolvedNative)?;
+    }
+    Ok(())
+}
+
+impl Machine {
+    /// Write the execution state: memory, program, ip, flags, fuel and the
+    /// open files as path, mode and offset. Host side configuration like I/O,
+    /// filesystem, sandbox, natives and breakpoints is not part of it
+    pub fn snapshot(&mut self, out: impl Write) -> Result<(), Error> {
+        let mut enc = Encoder::new(out);
+        enc.bytes(MAGIC)?;
+        enc.u64(VERSION)?;
+
+        enc.words(&self.registers)?;
+        enc.words(&self.stack.stack)?;
+        enc.usize(self.stack.sp)?;
+        enc.usize(self.stack.sbp)?;
+        enc.usize(self.stack.segments.len())?;
+        for (start, end) in &self.stack.segments {
+            enc.usize(*start)?;
+            enc.usize(*end)?;
+        }
+        enc.words(&self.heap)?;
+        enc.usize(self.hp)?;
+
+        enc.usize(self.data.len())?;
+        for segment in &self.data {
+            enc.words(segment)?;
+        }
+        enc.program(&self.program)?;
+
+        enc.usize(self.ip)?;
+        enc.bool(self.exit.is_some())?;
+        enc.word(self.exit.unwrap_or(Word::None))?;
+        enc.bool(self.halt)?;
+        enc.bool(self.debug)?;
+        enc.bool(self.verified)?;
+
+        enc.bool(self.fuel.is_some())?;
+        enc.u64(self.fuel.unwrap_or(0))?;
+        enc.u64(self.steps)?;
+
+        enc.usize(self.natives.len())?;
+        for (name, _) in &self.natives {
+            enc.str(name)?;
+        }
+
+        enc.usize(self.args.len())?;
+        for arg in &self.args {
+            enc.pointer(*arg)?;
+        }
+
+        let mut ids: Vec<usize> = self.files.keys().copied().collect();
+        ids.sort();
+        enc.usize(ids.len())?;
+        for id in ids {
+            let file = self.files.get_mut(&id).ok_or(Error::FileNotFound)?;
+            file.handle.flush().map_err(io_error)?;
+            let offset = file.handle.stream_position().map_err(io_error)?;
+
+            enc.usize(id)?;
+            enc.str(&file.path)?;
+            enc.u8(mode_tag(file.mode))?;
+            enc.u64(offset)?;
+        }
+        enc.usize(self.file_id_counter)
+    }
+
+    /// Replace the execution state with a snapshot. Natives the program
+    /// refers to are matched by name and have to be registered first, files
+    /// are reopened through the sandbox and filesystem of this machine
+    pub fn restore(&mut self, input: impl Read) -> Result<(), Error> {
+        let mut dec = Decoder::new(input);
+        if &dec.bytes::<8>()? != MAGIC || dec.u64()? != VERSION {
+            return Err(Error::InvalidBytecode);
+        }
+
+        let mut registers = dec.words()?;
+        let mut stack = Stack {
+            stack: dec.words()?,
+            sp: dec.usize()?,
+            sbp: dec.usize()?,
+            reads: None,
+            lowered: None,
+            segments: Vec::new(),
+        };
+        for _ in 0..dec.usize()? {
+            stack.segments.push((dec.usize()?, dec.usize()?));
+        }
+        let mut heap = dec.words()?;
+        let hp = dec.usize()?;
+
+        if stack.stack.len() != stack.sp || stack.sbp > stack.sp {
+            return Err(Error::InvalidBytecode);
+        }
+
+        // Allocation assumes the limits hold
+        if stack.sp > MAX_STACK || heap.len() > MAX_HEAP {
+            return Err(Error::InvalidBytecode);
+        }
+
+        // Segments lie in order below sp, each after its header
+        let mut low = 1;
+        for (start, end) in &stack.segments {
+            if *start < low || end < start || *end > stack.sp {
+                return Err(Error::InvalidBytecode);
+            }
+            low = end + 1;
+        }
+
+        let mut data = Vec::new();
+        for _ in 0..dec.usize()? {
+            data.push(dec.words()?);
+        }
+        let mut program = dec.program()?;
+
+        let ip = dec.usize()?;
+        let exited = dec.bool()?;
+        let mut exit = Some(dec.word()?).filter(|_| exited);
+        let halt = dec.bool()?;
+        let debug = dec.bool()?;
+        // The program is checked again, a snapshot is not trusted to say so
+        let _verified = dec.bool()?;
+
+        let limited = dec.bool()?;
+        let fuel = Some(dec.u64()?).filter(|_| limited);
+        let steps = dec.u64()?;
+
+        let mut table = Vec::new();
+        for _ in 0..dec.usize()? {
+            let name = dec.str()?;
+            table.push(self.natives.iter().position(|(n, _)| *n == name));
+        }
+
+        let mut args = Vec::new();
+        for _ in 0..dec.usize()? {
+            args.push(dec.pointer()?);
+        }
+
+        let mut records = Vec::new();
+        for _ in 0..dec.usize()? {
+            records.push(FileRecord {
+                id: dec.usize()?,
+                path: dec.str()?,
+                mode: mode_from_tag(dec.u8()?)?,
+                offset: dec.u64()?,
+            });
+        }
+        let file_id_counter = dec.usize()?;
+
+        for word in registers.iter_mut()
+            .chain(stack.stack.iter_mut())
+            .chain(heap.iter_mut())
+            .chain(data.iter_mut().flatten())
+            .chain(program.iter_mut().flat_map(|inst| inst.operand.iter_mut()))
+            .chain(exit.iter_mut())
+        {
+            remap_native(word, &table)?;
+        }
+
+        if let Some(max) = self.sandbox.max_open_files {
+            if records.len() > max {
+                return Err(Error::TooManyOpenFiles);
+            }
+        }
+
+        let mut files = HashMap::new();
+        for record in records {
+            // Truncating again would lose what was written before the snapshot
+            let reopen = match record.mode {
+                OpenMode::Truncate => OpenMode::Write,
+                mode => mode,
+            };
+            if !self.sandbox.allows(reopen) {
+                return Err(Error::PermissionDenied);
+            }
+
+            let path = self.sandbox.resolve(self.fs.as_ref(), &record.path)?;
+            let mut handle = self.fs.open(&path, reopen).map_err(io_error)?;
+            handle.seek(SeekFrom::Start(record.offset)).map_err(io_error)?;
+            files.insert(record.id, OpenFile { handle, path: record.path, mode: record.mode });
+        }
+
+        self.registers = registers;
+        self.stack = stack;
+        self.heap = heap;
+        self.heap_changed();
+        self.hp = hp;
+        self.data = data;
+        self.program = program;
+        self.ip = ip;
+        self.exit = exit;
+        self.halt = halt;
+        self.debug = debug;
+        self.verified = false;
+        self.fuel = fuel;
+        self.steps = steps;
+        self.args = args;
+        self.files = files;
+        self.clear_history();
+        self.file_id_counter = file_id_counter;
+        Ok(())
+    }
+
+    /// Write snapshot to a file on the host
+    pub fn save_snapshot(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = File::create(path).map_err(io_error)?;
+        let mut out = BufWriter::new(file);
+        self.snapshot(&mut out)?;
+        out.flush().map_err(io_error)
+    }
+
+    /// Restore snapshot from a file on the host
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = File::open(path).map_err(io_error)?;
+        self.restore(BufReader::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::run::Outcome;
+
+    #[test]
+    fn restore_verifies_again() {
+        let mut machine = Machine::new(vec![Inst::new(InstType::Exit, [Word::Int(0), Word::None])]);
+        machine.skip_verify();
+
+        let mut bytes = Vec::new();
+        machine.snapshot(&mut bytes).unwrap();
+
+        let mut restored = Machine::new(Vec::new());
+        restored.restore(bytes.as_slice()).unwrap();
+        assert!(!restored.verified);
+    }
+
+    #[test]
+    fn restored_machine_continues() {
+        let source = "  alloc 2\n  dup\n  pushi 1\n  pushi 9\n  storeidx\n  halt\n  \
+                      pushi 1\n  loadidx\n  pushi 1\n  plus\n  exit 0\n";
+        let mut machine = Machine::from_module(assemble(source, "test.asm").unwrap());
+        assert_eq!(machine.run(), Ok(Outcome::Halted));
+
+        let mut bytes = Vec::new();
+        machine.snapshot(&mut bytes).unwrap();
+
+        let mut restored = Machine::new(Vec::new());
+        restored.restore(bytes.as_slice()).unwrap();
+        assert_eq!(restored.ip(), 6);
+        assert_eq!(restored.resume(), Ok(Outcome::Exited(Word::Int(0))));
+        assert_eq!(restored.stack.stack, vec![Word::Int(10)]);
+    }
+
+    #[test]
+    fn oversized_stack_is_rejected() {
+        let mut machine = Machine::new(Vec::new());
+        machine.stack.stack = vec![Word::Int(0); MAX_STACK + 1];
+        machine.stack.sp = MAX_STACK + 1;
+
+        let mut bytes = Vec::new();
+        machine.snapshot(&mut bytes).unwrap();
+        assert_eq!(Machine::new(Vec::new()).restore(bytes.as_slice()), Err(Error::InvalidBytecode));
+    }
+}
@@ -0,0 +1,43 @@
+use super::*;
+
+/// Position in an assembly source file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLoc {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Source position of every instruction, by instruction index
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub locs: Vec<Option<SourceLoc>>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&SourceLoc> {
+        self.locs.get(index)?.as_ref()
+    }
+
+    pub fn insert(&mut self, index: usize, loc: SourceLoc) {
+        if index >= self.locs.len() {
+            self.locs.resize(index + 1, None);
+        }
+        self.locs[index] = Some(loc);
+    }
+}
+
+impl Machine {
+    /// Source positions reported for the loaded program, kept in its debug info
+    pub fn set_source_map(&mut self, source_map: Option<SourceMap>) {
+        self.debug_info.get_or_insert_with(DebugInfo::default).source_map = source_map.unwrap_or_default();
+    }
+
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.debug_info.as_ref().map(|debug_info| &debug_info.source_map)
+    }
+}
@@ -1,9 +1,27 @@
 use super::*;
 
+/// Max words on the stack
+pub const MAX_STACK: usize = 1 << 20;
+
+/// Slots from low up to the sp a log was started at, with the words they
+/// held then, saved as they are popped
+#[derive(Debug, Default)]
+pub struct Lowered {
+    pub low: usize,
+    pub words: Vec<Word>,
+}
+
+#[derive(Default)]
 pub struct Stack {
     pub stack: Vec<Word>,
     pub sp: usize,
     pub sbp: usize,
+    /// Slots read by pops and copies, while a log is taken
+    pub reads: Option<Vec<usize>>,
+    pub lowered: Option<Lowered>,
+    /// Bounds [start, end) of the live segments pushed by push_segment,
+    /// lowest first
+    pub segments: Vec<(usize, usize)>,
 }
 
 impl Stack {
@@ -12,10 +30,17 @@ impl Stack {
             stack: Vec::new(),
             sp: 0,
             sbp: 0,
+            reads: None,
+            lowered: None,
+            segments: Vec::new(),
         }
     }
 
     pub fn push(&mut self, value: Word) -> Result<(), Error> {
+        if self.sp >= MAX_STACK {
+            return Err(Error::StackOverflow);
+        }
+
         self.stack.push(value);
         self.sp += 1;
         Ok(())
@@ -27,7 +52,10 @@ impl Stack {
             return Err(Error::StackUnderflow);
         }
 
+        self.lower(self.sp - 1);
         self.sp -= 1;
+        self.forget_segments(self.sp);
+        self.read(self.sp);
         Ok(self.stack.remove(self.sp))
     }
 
@@ -37,8 +65,106 @@ impl Stack {
             return Err(Error::StackUnderflow);
         }
 
-        let value = self.stack[self.sp - 1].clone();
-        let _ = self.push(value)?;
+        self.read(self.sp - 1);
+        let value = self.stack[self.sp - 1];
+        self.push(value)
+    }
+
+    /// Save the slots from index up that are about to be removed, while a
+    /// log is taken
+    fn lower(&mut self, index: usize) {
+        if let Some(lowered) = &mut self.lowered {
+            if index < lowered.low {
+                lowered.words.splice(0..0, self.stack[index..lowered.low].iter().copied());
+                lowered.low = index;
+            }
+        }
+    }
+
+    /// Drop the segments whose words from index up are popped or moved
+    fn forget_segments(&mut self, index: usize) {
+        while self.segments.last().is_some_and(|(_, end)| *end > index) {
+            self.segments.pop();
+        }
+    }
+
+    /// Bounds of the pushed segment ptr points into or one past
+    pub fn segment(&self, ptr: usize) -> Option<(usize, usize)> {
+        let index = self.segments.partition_point(|(start, _)| *start <= ptr);
+        let (start, end) = self.segments[..index].last().copied()?;
+        (ptr <= end).then_some((start, end))
+    }
+
+    fn read(&mut self, index: usize) {
+        if let Some(reads) = &mut self.reads {
+            reads.push(index);
+        }
+    }
+
+    /// First slot the current frame may touch. Inside a call sbp holds the
+    /// return address and the saved sbp is below it, outside any call sbp
+    /// is 0 and the whole stack is usable
+    pub fn frame_base(&self) -> usize {
+        match self.sbp {
+            0 => 0,
+            sbp => sbp.saturating_add(1),
+        }
+    }
+
+    /// Fail unless the current frame holds n values
+    fn require(&self, n: usize) -> Result<(), Error> {
+        let base = self.frame_base();
+        if self.sp < base || self.sp - base < n {
+            return Err(Error::StackUnderflow);
+        }
+
+        Ok(())
+    }
+
+    /// a b -> b a
+    pub fn swap(&mut self) -> Result<(), Error> {
+        self.roll(1)
+    }
+
+    /// a b -> a b a
+    pub fn over(&mut self) -> Result<(), Error> {
+        self.pick(1)
+    }
+
+    /// a b c -> b c a
+    pub fn rot(&mut self) -> Result<(), Error> {
+        self.roll(2)
+    }
+
+    /// Push copy of the nth value below the top, pick 0 is dup
+    pub fn pick(&mut self, n: usize) -> Result<(), Error> {
+        self.require(n.saturating_add(1))?;
+
+        self.read(self.sp - 1 - n);
+        let value = self.stack[self.sp - 1 - n];
+        self.push(value)
+    }
+
+    /// Move the nth value below the top to the top, roll 1 is swap
+    pub fn roll(&mut self, n: usize) -> Result<(), Error> {
+        self.require(n.saturating_add(1))?;
+
+        self.read(self.sp - 1 - n);
+        self.lower(self.sp - 1 - n);
+        self.forget_segments(self.sp - 1 - n);
+        let value = self.stack.remove(self.sp - 1 - n);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Pop n values
+    pub fn drop_n(&mut self, n: usize) -> Result<(), Error> {
+        self.require(n)?;
+
+        self.lower(self.sp - n);
+        self.sp -= n;
+        self.forget_segments(self.sp);
+        self.stack.truncate(self.sp);
         Ok(())
     }
 
@@ -49,7 +175,10 @@ impl Stack {
     
     /// Leave Stack Frame
     pub fn pop_frame(&mut self) {
-        self.sp = self.sbp;
+        self.lower(self.sbp.min(self.sp));
+        self.sp = self.sbp.min(self.sp);
+        self.stack.truncate(self.sp);
+        self.forget_segments(self.sp);
     }
 
     // Push arr to stack, Pointing to the first element
@@ -61,7 +190,52 @@ impl Stack {
         for &elem in arr.iter() {
             self.push(elem)?;
         }
-        
+        self.segments.push((self.sp - arr.len(), self.sp));
+
         Ok(ptr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    /// Exit code, or the error with the index of the failing instruction
+    fn run(source: &str) -> Result<Word, (Error, Option<usize>)> {
+        let mut machine = Machine::from_module(assemble(source, "test.asm").unwrap());
+        machine.skip_verify();
+        match machine.run() {
+            Ok(crate::run::Outcome::Exited(code)) => Ok(code),
+            Ok(outcome) => panic!("unexpected {:?}", outcome),
+            Err(err) => Err((err, machine.fault())),
+        }
+    }
+
+    #[test]
+    fn callee_cannot_drop_return_address() {
+        let source = "  call f\n  exit 0\nf: dropn 1\n  return\n";
+        assert_eq!(run(source), Err((Error::StackUnderflow, Some(2))));
+    }
+
+    #[test]
+    fn callee_cannot_swap_return_address() {
+        let source = "  call f\n  exit 0\nf: pushi 1\n  swap\n  pop\n  return\n";
+        assert_eq!(run(source), Err((Error::StackUnderflow, Some(3))));
+
+        let source = "  call f\n  exit 0\nf: pushi 1\n  pushi 2\n  rot\n  return\n";
+        assert_eq!(run(source), Err((Error::StackUnderflow, Some(4))));
+    }
+
+    #[test]
+    fn callee_uses_its_own_values() {
+        let source = "  pushi 7\n  call f\n  exit 0\nf: pushi 1\n  pushi 2\n  swap\n  dropn 2\n  return\n";
+        assert_eq!(run(source), Ok(Word::Int(0)));
+    }
+
+    #[test]
+    fn top_level_uses_whole_stack() {
+        let source = "  pushi 1\n  pushi 2\n  swap\n  dropn 2\n  exit 0\n";
+        assert_eq!(run(source), Ok(Word::Int(0)));
+    }
+}
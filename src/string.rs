@@ -0,0 +1,163 @@
+use super::*;
+use crate::format::canonical;
+use crate::utils::to_string;
+use std::cmp::Ordering;
+
+impl Machine {
+    /// Read char segment as String
+    pub fn read_string(&self, ptr: Pointer) -> Result<String, Error> {
+        to_string(&self.read_arr(ptr)?)
+    }
+
+    /// Allocate char segment on the Heap
+    pub fn alloc_string(&mut self, string: &str) -> Result<Pointer, Error> {
+        let str_arr: Vec<Word> = string.chars().map(Word::Char).collect();
+        self.alloc_segment(&str_arr)
+    }
+
+    pub fn strlen(&self, ptr: Pointer) -> Result<i64, Error> {
+        Ok(self.read_string(ptr)?.chars().count() as i64)
+    }
+
+    /// Concatenate into new heap segment
+    pub fn strcat(&mut self, a: Pointer, b: Pointer) -> Result<Pointer, Error> {
+        let string = self.read_string(a)? + &self.read_string(b)?;
+        self.alloc_string(&string)
+    }
+
+    /// Compare lexicographically, returns -1, 0 or 1
+    pub fn strcmp(&self, a: Pointer, b: Pointer) -> Result<i64, Error> {
+        Ok(match self.read_string(a)?.cmp(&self.read_string(b)?) {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        })
+    }
+
+    /// Copy len chars starting at start into new heap segment
+    pub fn substr(&mut self, ptr: Pointer, start: i64, len: i64) -> Result<Pointer, Error> {
+        let arr = self.read_arr(ptr)?;
+        if start < 0 || len < 0 || (start as usize).saturating_add(len as usize) > arr.len() {
+            return Err(Error::SegmentationFault);
+        }
+
+        let start = start as usize;
+        let sub = to_string(&arr[start..start + len as usize])?;
+        self.alloc_string(&sub)
+    }
+
+    /// Char index of first occurrence of needle, -1 if not found
+    pub fn strfind(&self, haystack: Pointer, needle: Pointer) -> Result<i64, Error> {
+        let haystack = self.read_string(haystack)?;
+        let needle = self.read_string(needle)?;
+
+        Ok(match haystack.find(&needle) {
+            Some(byte_index) => haystack[..byte_index].chars().count() as i64,
+            None => -1,
+        })
+    }
+
+    /// Map chars into new heap segment
+    pub fn strmap(&mut self, ptr: Pointer, upper: bool) -> Result<Pointer, Error> {
+        let string = self.read_string(ptr)?;
+        let mapped = if upper {
+            string.to_uppercase()
+        } else {
+            string.to_lowercase()
+        };
+
+        self.alloc_string(&mapped)
+    }
+
+    /// Char classification, used by the Is instructions
+    pub fn classify(kind: &InstType, c: char) -> Result<bool, Error> {
+        Ok(match kind {
+            InstType::Isdigit => c.is_ascii_digit(),
+            InstType::Isalpha => c.is_alphabetic(),
+            InstType::Isalnum => c.is_alphanumeric(),
+            InstType::Isspace => c.is_whitespace(),
+            InstType::Isupper => c.is_uppercase(),
+            InstType::Islower => c.is_lowercase(),
+            _ => return Err(Error::IllegalInst),
+        })
+    }
+
+    pub fn atoi(&self, ptr: Pointer) -> Result<i64, Error> {
+        self.read_string(ptr)?
+            .trim()
+            .parse()
+            .map_err(|_| Error::ParseError)
+    }
+
+    pub fn atof(&self, ptr: Pointer) -> Result<f64, Error> {
+        self.read_string(ptr)?
+            .trim()
+            .parse()
+            .map_err(|_| Error::ParseError)
+    }
+
+    /// Number to new heap segment in canonical form
+    pub fn itoa(&mut self, value: Word) -> Result<Pointer, Error> {
+        match value {
+            Word::Int(_) | Word::Float(_) | Word::Double(_) => self.alloc_string(&canonical(value)),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(machine: &mut Machine, string: &str) -> Pointer {
+        machine.alloc_string(string).unwrap()
+    }
+
+    #[test]
+    fn substr_stays_inside_the_string() {
+        let mut machine = Machine::new(Vec::new());
+        let ptr = string(&mut machine, "héllo");
+
+        let sub = machine.substr(ptr, 1, 3).unwrap();
+        assert_eq!(machine.read_string(sub).unwrap(), "éll");
+        let sub = machine.substr(ptr, 5, 0).unwrap();
+        assert_eq!(machine.read_string(sub).unwrap(), "");
+
+        assert_eq!(machine.substr(ptr, 3, 3), Err(Error::SegmentationFault));
+        assert_eq!(machine.substr(ptr, -1, 2), Err(Error::SegmentationFault));
+        assert_eq!(machine.substr(ptr, 0, -1), Err(Error::SegmentationFault));
+        assert_eq!(machine.substr(ptr, i64::MAX, i64::MAX), Err(Error::SegmentationFault));
+    }
+
+    #[test]
+    fn strfind_counts_chars() {
+        let mut machine = Machine::new(Vec::new());
+        let haystack = string(&mut machine, "ñandú über");
+
+        let needle = string(&mut machine, "über");
+        assert_eq!(machine.strfind(haystack, needle), Ok(6));
+        let needle = string(&mut machine, "");
+        assert_eq!(machine.strfind(haystack, needle), Ok(0));
+        let needle = string(&mut machine, "uber");
+        assert_eq!(machine.strfind(haystack, needle), Ok(-1));
+    }
+
+    #[test]
+    fn atoi_and_atof_reject_garbage() {
+        let mut machine = Machine::new(Vec::new());
+
+        let ptr = string(&mut machine, " -42\n");
+        assert_eq!(machine.atoi(ptr), Ok(-42));
+        let ptr = string(&mut machine, "2.5");
+        assert_eq!(machine.atof(ptr), Ok(2.5));
+
+        for text in ["", "4 2", "0x10", "99999999999999999999", "2.5"] {
+            let ptr = string(&mut machine, text);
+            assert_eq!(machine.atoi(ptr), Err(Error::ParseError), "{:?}", text);
+        }
+        for text in ["", "1.2.3", "one"] {
+            let ptr = string(&mut machine, text);
+            assert_eq!(machine.atof(ptr), Err(Error::ParseError), "{:?}", text);
+        }
+    }
+}
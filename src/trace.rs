@@ -0,0 +1,209 @@
+use super::*;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Writes one JSON object per executed instruction
+pub struct Tracer {
+    out: Box<dyn Write>,
+    /// Only trace instructions at these indices
+    pub ips: Option<Range<usize>>,
+    /// Only trace these instruction types
+    pub ops: Option<HashSet<InstType>>,
+}
+
+/// State before a traced instruction
+pub struct TraceStart {
+    ip: usize,
+    sp: usize,
+    registers: Vec<Word>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + 'static) -> Self {
+        Tracer { out: Box::new(out), ips: None, ops: None }
+    }
+
+    fn wants(&self, ip: usize, inst_type: InstType) -> bool {
+        self.ips.as_ref().is_none_or(|ips| ips.contains(&ip))
+            && self.ops.as_ref().is_none_or(|ops| ops.contains(&inst_type))
+    }
+}
+
+/// JSON string literal
+fn json_string(string: &str) -> String {
+    let mut out = String::with_capacity(string.len() + 2);
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Words are written in their Debug form, e.g. "Int(3)"
+fn json_word(word: Option<Word>) -> String {
+    match word {
+        Some(word) => json_string(&format!("{:?}", word)),
+        None => "null".to_string(),
+    }
+}
+
+impl Machine {
+    /// Trace executed instructions, None stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Trace every executed instruction to a file on the host
+    pub fn trace_to_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = File::create(path).map_err(|_| Error::IO)?;
+        self.tracer = Some(Tracer::new(BufWriter::new(file)));
+        Ok(())
+    }
+
+    /// Remember the state the record is compared against, None if the
+    /// instruction is not traced
+    pub fn trace_start(&mut self, inst: &Inst) -> Option<TraceStart> {
+        if !self.tracer.as_ref()?.wants(self.ip, inst.inst_type) {
+            return None;
+        }
+
+        self.journal.get_or_insert_with(Vec::new).clear();
+        Some(TraceStart {
+            ip: self.ip,
+            sp: self.stack.sp,
+            registers: self.registers.clone(),
+        })
+    }
+
+    /// Write the record of an executed instruction, writes is the journal
+    /// taken while it executed
+    pub fn trace_end(
+        &mut self,
+        start: TraceStart,
+        inst: &Inst,
+        result: &Result<(), Error>,
+        writes: &[(Pointer, Option<Word>)],
+    ) -> Result<(), Error> {
+
+        let mut record = format!(
+            "{{\"step\":{},\"ip\":{},\"op\":\"{:?}\",\"operands\":[{},{}],\"stack_delta\":{},\"top\":{}",
+            self.steps,
+            start.ip,
+            inst.inst_type,
+            json_word(Some(inst.operand[0])),
+            json_word(Some(inst.operand[1])),
+            self.stack.sp as i64 - start.sp as i64,
+            json_word(self.stack.stack.last().copied()),
+        );
+
+        if let Some(loc) = self.location(start.ip) {
+            let loc = format!("{}:{}:{}", loc.file, loc.line, loc.col);
+            record.push_str(&format!(",\"loc\":{}", json_string(&loc)));
+        }
+
+        let registers: Vec<String> = start.registers.iter()
+            .zip(&self.registers)
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(reg, (old, new))| format!(
+                "{{\"reg\":{},\"old\":{},\"new\":{}}}",
+                reg,
+                json_word(Some(*old)),
+                json_word(Some(*new)),
+            ))
+            .collect();
+        record.push_str(&format!(",\"registers\":[{}]", registers.join(",")));
+
+        // Registers are compared above
+        let writes: Vec<String> = writes.iter()
+            .filter(|(ptr, _)| !matches!(ptr, Pointer::Register(_)))
+            .map(|(ptr, old)| {
+                let new = match ptr {
+                    Pointer::Heap(i) => self.heap.get(*i),
+                    _ => self.stack.stack.get(ptr.as_usize()),
+                };
+                format!(
+                    "{{\"ptr\":{},\"old\":{},\"new\":{}}}",
+                    json_string(&format!("{:?}", ptr)),
+                    json_word(*old),
+                    json_word(new.copied()),
+                )
+            })
+            .collect();
+        record.push_str(&format!(",\"writes\":[{}]", writes.join(",")));
+
+        if let Err(err) = result {
+            record.push_str(&format!(",\"error\":\"{:?}\"", err));
+        }
+        record.push('}');
+
+        match &mut self.tracer {
+            Some(tracer) => writeln!(tracer.out, "{}", record).map_err(|_| Error::IO),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SharedBuffer;
+
+    fn trace(source: &str, setup: impl FnOnce(&mut Tracer)) -> Vec<String> {
+        let mut machine = Machine::from_module(crate::asm::assemble(source, "test.asm").unwrap());
+        machine.skip_verify();
+        let out = SharedBuffer::new();
+        let mut tracer = Tracer::new(out.clone());
+        setup(&mut tracer);
+        machine.set_tracer(Some(tracer));
+        let _ = machine.run();
+        out.contents_string().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn records_are_numbered_by_step() {
+        let lines = trace("mov r0, 2\nloop: pushr r0\npushi -1\nplus\npopr r0\npushr r0\njeq loop\n", |_| ());
+        assert_eq!(lines.len(), 13);
+        for (step, line) in lines.iter().enumerate() {
+            assert!(line.starts_with(&format!("{{\"step\":{},", step + 1)), "{}", line);
+        }
+
+        assert_eq!(
+            lines[0],
+            "{\"step\":1,\"ip\":0,\"op\":\"Mov\",\"operands\":[\"Ptr(Register(0))\",\"Int(2)\"],\"stack_delta\":0,\
+             \"top\":null,\"loc\":\"test.asm:1:1\",\"registers\":[{\"reg\":0,\"old\":\"Free\",\"new\":\"Int(2)\"}],\"writes\":[]}",
+        );
+        assert!(lines[7].starts_with("{\"step\":8,\"ip\":1,\"op\":\"Pushr\","), "{}", lines[7]);
+    }
+
+    #[test]
+    fn filters_keep_the_step_count() {
+        let source = "mov r0, 2\nloop: pushr r0\npushi -1\nplus\npopr r0\npushr r0\njeq loop\n";
+        let lines = trace(source, |tracer| tracer.ops = Some(HashSet::from([InstType::Plus])));
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"step\":4,\"ip\":3,"), "{}", lines[0]);
+        assert!(lines[1].starts_with("{\"step\":10,\"ip\":3,"), "{}", lines[1]);
+
+        let lines = trace(source, |tracer| tracer.ips = Some(0..1));
+        assert_eq!(lines.len(), 1);
+    }
+
+    #[test]
+    fn failed_instructions_are_traced() {
+        let lines = trace("alloc 1\npopr r0\npushc 'a'\nset r0\npushi 1\npopr r0\npushi 1\nset r0\n", |_| ());
+        assert!(lines[3].contains("\"writes\":[{\"ptr\":\"Heap(1)\",\"old\":\"Int(0)\",\"new\":\"Char('a')\"}]"), "{}", lines[3]);
+        assert!(lines[7].ends_with(",\"error\":\"TypeMismatch\"}"), "{}", lines[7]);
+    }
+}
@@ -1,94 +1,139 @@
 use super::*;
-use std::io::Write;
-use std::io::stdin;
-use std::fs::OpenOptions;
+use crate::backend::{io_error, OpenMode};
+
+/// Convert char segment to String
+pub fn to_string(arr: &[Word]) -> Result<String, Error> {
+    arr.iter()
+        .map(|word| match word {
+            Word::Char(c) => Ok(*c),
+            _ => Err(Error::TypeMismatch),
+        })
+        .collect()
+}
 
 impl Machine {
 
     // Open file and return Pointer::Files 
     pub fn open(&mut self, data_ptr: Pointer, mode: i64) -> Result<Pointer, Error> {
-        let arr = self.read_arr(data_ptr)?;
-        
-        let filename: String = arr.iter()
-            .map(|word| Ok(match word {
-                Word::Char(c) => *c,
-                _ => return Err(Error::TypeMismatch),
-            }))
-            .collect::<Result<String, Error>>()?;
-
-        let file_ptr = match mode {
-            0 => OpenOptions::new().read(true).open(filename),
-            1 => OpenOptions::new().write(true).create(true).truncate(true).open(filename),
-            2 => OpenOptions::new().write(true).create(true).append(true).open(filename),
-            _ => return Err(Error::IllegalInst)
-        };
-
-        match file_ptr {
-            Ok(f) => {
-                let file_id = self.file_id_counter;
-                self.files.insert(file_id, f);
-                self.file_id_counter += 1;
-                Ok(Pointer::Files(file_id))
+        let mode = OpenMode::from_i64(mode).ok_or(Error::IllegalInst)?;
+        let path = self.read_string(data_ptr)?;
+
+        let ptr = self.host(HostCall::Open, |m| {
+            if !m.sandbox.allows(mode) {
+                return Err(Error::PermissionDenied);
+            }
+
+            if let Some(max) = m.sandbox.max_open_files {
+                if m.files.len() >= max {
+                    return Err(Error::TooManyOpenFiles);
+                }
             }
-            Err(_) => Err(Error::FileNotFound),
+
+            let resolved = m.sandbox.resolve(m.fs.as_ref(), &path)?;
+            let handle = m.fs.open(&resolved, mode)
+                .map_err(io_error)?;
+
+            let file_id = m.file_id_counter;
+            m.files.insert(file_id, OpenFile { handle, path: path.clone(), mode });
+            m.file_id_counter += 1;
+            Ok(Pointer::Files(file_id))
+        })?;
+
+        // Replayed files get an entry too, so the table matches the recording.
+        // Their calls are answered from the log and never reach the handle
+        if let (true, Pointer::Files(file_id)) = (self.replaying(), ptr) {
+            let handle = Box::new(std::io::Cursor::new(Vec::new()));
+            self.files.insert(file_id, OpenFile { handle, path, mode });
+            self.file_id_counter = self.file_id_counter.max(file_id + 1);
+        }
+
+        Ok(ptr)
+    }
+
+    /// Pointer given as operand, or popped off the stack if there is none
+    pub fn pointer_operand(&mut self, operand: Word) -> Result<Pointer, Error> {
+        match operand {
+            Word::Ptr(ptr) => Ok(ptr),
+            Word::None => self.pop_ptr(),
+            _ => Err(Error::IllegalInst),
+        }
+    }
+
+    /// Pop Int off the stack
+    pub fn pop_int(&mut self) -> Result<i64, Error> {
+        match self.stack.pop()? {
+            Word::Int(val) => Ok(val),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    /// Pop Pointer off the stack
+    pub fn pop_ptr(&mut self) -> Result<Pointer, Error> {
+        match self.stack.pop()? {
+            Word::Ptr(ptr) => Ok(ptr),
+            _ => Err(Error::TypeMismatch),
+        }
+    }
+
+    /// Pop word count off the stack
+    pub fn pop_len(&mut self) -> Result<usize, Error> {
+        match self.pop_int()? {
+            len if len >= 0 => Ok(len as usize),
+            _ => Err(Error::SegmentationFault),
         }
     }
 
     /// Close Open Files
     pub fn close(&mut self, ptr: Pointer) -> Result<(), Error> {
-        if let Pointer::Files(file_ptr) = ptr {
-            if let Some(file) = self.files.remove(&file_ptr) {
-                return Ok(()); 
+        if let (true, Pointer::Files(file_id)) = (self.replaying(), ptr) {
+            self.files.remove(&file_id);
+        }
+
+        self.host(HostCall::Close, |m| {
+            if let Pointer::Files(file_ptr) = ptr {
+                if m.files.remove(&file_ptr).is_some() {
+                    return Ok(()); 
+                }
+                
+            }
+            else {
+                return Err(Error::FileNotFound);
             }
             
-        }
-        else {
-            return Err(Error::FileNotFound);
-        }
-        
-        Ok(())
+            Ok(())
+        })
     }
 
-    /// Read from Stdin returns pointer
-    pub fn read(&mut self) -> Result<usize, Error> {
-        let mut buffer = String::new();
+    /// Read line from Stdin onto the stack, returns pointer to the segment
+    pub fn read(&mut self) -> Result<Pointer, Error> {
+        let buffer = self.host(HostCall::Read, |m| {
+            let mut buffer = String::new();
 
-        stdin().read_line(&mut buffer)
-            .map_err(|_| { Error::IO } 
-        )?;
-        
-        if buffer.ends_with('\n') {
-            buffer.pop();
-        }
+            m.input.read_line(&mut buffer)
+                .map_err(|_| { Error::IO } 
+            )?;
+            
+            if buffer.ends_with('\n') {
+                buffer.pop();
+            }
+            Ok(buffer)
+        })?;
 
         let str_arr: Vec<Word> = buffer.chars().map(Word::Char).collect();
-        let _ = self.stack.push_segment(&str_arr);
-
-        Ok(self.stack.sp) 
+        self.stack.push_segment(&str_arr)
     }
 
     /// Write to stdout from string ptr
-    pub fn write(&self, ptr: Pointer) -> Result<(), Error> {
-        let arr = self.read_arr(ptr)?;
-
-        // Convert to String
-        let string: String = arr.iter()
-            .map(|word| Ok(match word {
-                Word::Char(c) => *c,
-                _ => return Err(Error::TypeMismatch),
-            }))
-            .collect::<Result<String, Error>>()?;
+    pub fn write(&mut self, ptr: Pointer) -> Result<(), Error> {
+        let string = to_string(&self.read_arr(ptr)?)?;
 
         // Write the string to stdout
-        write!(std::io::stdout(), "{}", string)
-            .map_err(|_| Error::IO)?;
-
-        Ok(())
+        self.print(&string)
     }
 
+    /// Stop execution, the exit code is reported by run()
     pub fn exit(&mut self, exit_code: Word) {
-        let _ = self.stack.push(exit_code);
-        self.exit = true;
+        self.exit = Some(exit_code);
     }
 
     /// Do Binary Operation based on Word-type 
@@ -100,37 +145,23 @@ impl Machine {
             let right = self.stack.pop()?;
             let left = self.stack.pop()?;
             
-            match (&left, &right) {
-                (Word::Int(_), Word::Int(_)) => op(left.clone(), right.clone()).and_then(|res| self.stack.push(res)),
-                (Word::Float(_), Word::Float(_)) => op(left.clone(), right.clone()).and_then(|res| self.stack.push(res)),
-                (Word::Double(_), Word::Double(_)) => op(left.clone(), right.clone()).and_then(|res| self.stack.push(res)),
-                (Word::Int(a), Word::Float(_)) => {
-                    let a_float = *a as f32; 
-                    let result = op(Word::Float(a_float), right.clone())?;
-                    self.stack.push(result)
-                }
-                (Word::Float(_), Word::Int(b)) => {
-                    let b_float = *b as f32;
-                    let result = op(left.clone(), Word::Float(b_float))?;
-                    self.stack.push(result)
-                }
-                (Word::Float(a), Word::Double(_)) => {
-                    let a_double = *a as f64; 
-                    let result = op(Word::Double(a_double), right.clone())?;
-                    self.stack.push(result)
-                }
-                (Word::Double(_), Word::Float(b)) => {
-                    let b_double = *b as f64;
-                    let result = op(left.clone(), Word::Double(b_double))?;
-                    self.stack.push(result)
-                }
-                _ => Err(Error::IllegalInst), 
-            }
+            let result = arith::promote(op, left, right)?;
+            self.stack.push(result)
     }
 
 
     pub fn dump(&self) {
 
+        println!("\nNext: {}", self.describe_location(self.ip));
+
+        println!("\nRegisters:");
+        for (i, value) in self.registers.iter().enumerate() {
+            match value {
+                Word::Free => continue,
+                _ => println!("  r{} - {:?}", i, value),
+            }
+        }
+
         println!("\nStack:");
         if self.stack.sp < 1 {
             println!("  [empty]");
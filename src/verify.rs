@@ -0,0 +1,367 @@
+use super::*;
+use crate::format::placeholder_count;
+use crate::utils::to_string;
+
+/// Problem found by the verifier, with the index of the instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub index: usize,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    /// Operand slot holds a Word variant the instruction does not take
+    IllegalOperand(usize),
+    /// Jump or Call outside the program
+    IllegalTarget(i64),
+    IllegalRegister(usize),
+    IllegalData(usize),
+    /// Instruction pops more than the stack holds on some path
+    StackUnderflow { depth: usize, needed: usize },
+    /// Paths reach the instruction with different stack depths
+    StackMismatch { expected: usize, found: usize },
+}
+
+/// What an operand slot accepts
+#[derive(Clone, Copy)]
+enum Operand {
+    None,
+    Any,
+    Int,
+    Float,
+    Double,
+    Char,
+    /// Non negative Int or None, count popped off the stack if None
+    Count,
+    Target,
+    Register,
+    Data,
+    Ptr,
+    /// Pointer or None, popped off the stack if None
+    PtrOrNone,
+    /// Seek whence or None
+    Whence,
+    /// Function name in data, or an already linked native
+    Native,
+}
+
+fn operands(inst_type: InstType) -> [Operand; 2] {
+    use InstType::*;
+    use Operand as O;
+
+    match inst_type {
+        Pushi => [O::Int, O::None],
+        Pushf => [O::Float, O::None],
+        Pushd => [O::Double, O::None],
+        Pushc => [O::Char, O::None],
+        Pushr | Popr | Set | Storer => [O::Register, O::None],
+        Pushs => [O::Data, O::None],
+        Pick | Roll | DropN | Argv => [O::Count, O::None],
+        Jmp | Jeq | Jne | Call => [O::Target, O::None],
+        Exit => [O::Any, O::None],
+        Alloc => [O::Int, O::None],
+        Free | Close | Write => [O::Ptr, O::None],
+        Mov => [O::Register, O::Any],
+        Loadr => [O::Register, O::Ptr],
+        Movr | Load | Store | Addr | Subr | Mulr | Divr => [O::Register, O::Register],
+        Len | Open | Readf | Tell | Fsize | Exists | Remove | Mkdir | Listdir |
+        Printf | Strlen | Substr | Upper | Lower | Atoi | Atof | GetEnv => [O::PtrOrNone, O::None],
+        Writef | Rename | Strcat | Strcmp | Streq | Strfind => [O::PtrOrNone, O::PtrOrNone],
+        Seek => [O::PtrOrNone, O::Whence],
+        CallNative => [O::Native, O::Count],
+        _ => [O::None, O::None],
+    }
+}
+
+/// Words popped by operands given as None
+fn popped(inst: &Inst, slot: usize) -> usize {
+    match (operands(inst.inst_type)[slot], inst.operand[slot]) {
+        (Operand::PtrOrNone | Operand::Count, Word::None) => 1,
+        _ => 0,
+    }
+}
+
+/// Immediate count operand
+fn count(inst: &Inst) -> Option<usize> {
+    match inst.operand[0] {
+        Word::Int(n) if n >= 0 => Some(n as usize),
+        _ => None,
+    }
+}
+
+/// Stack depth at an instruction, None if it cannot be known statically
+type Depth = Option<usize>;
+
+struct Verifier<'a> {
+    program: &'a [Inst],
+    data: &'a [Vec<Word>],
+    registers: usize,
+    errors: Vec<VerifyError>,
+}
+
+impl Verifier<'_> {
+    fn report(&mut self, index: usize, kind: VerifyErrorKind) {
+        self.errors.push(VerifyError { index, kind });
+    }
+
+    fn check_operands(&mut self, index: usize) {
+        let inst = &self.program[index];
+        let kinds = operands(inst.inst_type);
+
+        for (slot, kind) in kinds.into_iter().enumerate() {
+            let word = inst.operand[slot];
+            let valid = match (kind, word) {
+                (Operand::None, Word::None) => true,
+                (Operand::Any, _) => true,
+                (Operand::Int, Word::Int(_)) => true,
+                (Operand::Float, Word::Float(_)) => true,
+                (Operand::Double, Word::Double(_)) => true,
+                (Operand::Char, Word::Char(_)) => true,
+                (Operand::Count, Word::Int(n)) => n >= 0,
+                (Operand::Count, Word::None) => true,
+                (Operand::Whence, Word::Int(n)) => (0..=2).contains(&n),
+                (Operand::Whence, Word::None) => true,
+                (Operand::Target, Word::Int(addr)) => {
+                    if addr < 0 || addr as usize >= self.program.len() {
+                        self.report(index, VerifyErrorKind::IllegalTarget(addr));
+                    }
+                    true
+                }
+                (Operand::Register, Word::Ptr(Pointer::Register(reg))) => {
+                    if reg >= self.registers {
+                        self.report(index, VerifyErrorKind::IllegalRegister(reg));
+                    }
+                    true
+                }
+                (Operand::Data | Operand::Native, Word::Ptr(Pointer::Data(_))) => true,
+                (Operand::Native, Word::Ptr(Pointer::Native(_))) => true,
+                (Operand::Ptr | Operand::PtrOrNone, Word::Ptr(_)) => true,
+                (Operand::PtrOrNone, Word::None) => true,
+                _ => false,
+            };
+
+            if !valid {
+                self.report(index, VerifyErrorKind::IllegalOperand(slot));
+            }
+
+            // Any data pointer given as operand has to exist
+            if let Word::Ptr(Pointer::Data(data_ptr)) = word {
+                if data_ptr >= self.data.len() {
+                    self.report(index, VerifyErrorKind::IllegalData(data_ptr));
+                }
+            }
+        }
+    }
+
+    /// Words popped and pushed, None if it depends on runtime values
+    fn stack_effect(&self, inst: &Inst) -> Option<(usize, usize)> {
+        use InstType::*;
+
+        let p0 = popped(inst, 0);
+        let p1 = popped(inst, 1);
+
+        Some(match inst.inst_type {
+            Pushi | Pushf | Pushd | Pushc | Pushr | Alloc | Argc | Eof |
+            Scani | Scanf | Scand | Scanc => (0, 1),
+            Pushs => match inst.operand[0] {
+                Word::Ptr(Pointer::Data(d)) => (0, self.data.get(d)?.len() + 2),
+                _ => return None,
+            },
+            Pop | Popr | Set | Print => (1, 0),
+            Dup => (1, 2),
+            Swap => (2, 2),
+            Over => (2, 3),
+            Rot => (3, 3),
+            Pick => count(inst).map(|n| (n + 1, n + 2))?,
+            Roll => count(inst).map(|n| (n + 1, n + 1))?,
+            DropN => count(inst).map(|n| (n, 0))?,
+            Plus | Sub | Mul | Div | And | Or | Xor | Cmp => (2, 1),
+            Not | Itoa | Isdigit | Isalpha | Isalnum | Isspace | Isupper | Islower => (1, 1),
+            Jeq | Jne => (1, 0),
+            Jmp | Halt | Return | Free | Close | Write | Mov | Loadr | Storer |
+            Movr | Load | Store | Addr | Subr | Mulr | Divr | Exit => (0, 0),
+            Memcpy | Memmove | Memset | StoreIdx => (3, 0),
+            Memcmp => (3, 1),
+            Realloc | PtrAdd | PtrSub | PtrDiff | LoadIdx => (2, 1),
+            PushAll => (0, self.registers),
+            PopAll => (self.registers, 0),
+            Open | Seek => (1 + p0, 1),
+            Len | Tell | Fsize | Exists | Listdir | Strlen | Upper | Lower |
+            Atoi | Atof | GetEnv | Argv => (p0, 1),
+            Remove | Mkdir => (p0, 0),
+            Writef | Rename => (p0 + p1, 0),
+            Strcat | Strcmp | Streq | Strfind => (p0 + p1, 1),
+            Substr => (2 + p0, 1),
+            Printf => match inst.operand[0] {
+                Word::Ptr(Pointer::Data(d)) => {
+                    let fmt = to_string(self.data.get(d)?).ok()?;
+                    (placeholder_count(&fmt).ok()?, 0)
+                }
+                _ => return None,
+            },
+
+            // Push segments of runtime length, or a runtime number of results
+            Readf | Read | Reada | CallNative | Call => return None,
+        })
+    }
+
+    /// Instructions control can continue at
+    fn successors(&self, index: usize) -> Vec<usize> {
+        let inst = &self.program[index];
+        let target = match inst.operand[0] {
+            Word::Int(addr) if addr >= 0 && (addr as usize) < self.program.len() => Some(addr as usize),
+            _ => None,
+        };
+
+        let next = Some(index + 1).filter(|&next| next < self.program.len());
+        match inst.inst_type {
+            InstType::Halt | InstType::Exit | InstType::Return => Vec::new(),
+            InstType::Jmp => target.into_iter().collect(),
+            InstType::Jeq | InstType::Jne => next.into_iter().chain(target).collect(),
+            _ => next.into_iter().collect(),
+        }
+    }
+
+    /// Track stack depth along all paths from the entry point and from every
+    /// Call target, whose depth relative to the caller is unknown
+    fn check_stack(&mut self) {
+        if self.program.is_empty() {
+            return;
+        }
+
+        let mut depths: Vec<Option<Depth>> = vec![None; self.program.len()];
+        let mut worklist = vec![(0, Some(0))];
+
+        for inst in self.program {
+            if let (InstType::Call, Word::Int(addr)) = (inst.inst_type, inst.operand[0]) {
+                if addr >= 0 && (addr as usize) < self.program.len() {
+                    worklist.push((addr as usize, None));
+                }
+            }
+        }
+
+        while let Some((index, depth)) = worklist.pop() {
+            let depth = match depths[index] {
+                None => depth,
+                Some(None) => continue,
+                Some(seen) if seen == depth => continue,
+                Some(Some(expected)) => {
+                    if let Some(found) = depth {
+                        self.report(index, VerifyErrorKind::StackMismatch { expected, found });
+                    }
+
+                    // Stop following paths that already disagree
+                    depths[index] = Some(None);
+                    None
+                }
+            };
+
+            depths[index] = Some(depth);
+
+            let inst = &self.program[index];
+            let after = match (depth, self.stack_effect(inst)) {
+                (Some(depth), Some((pops, _))) if depth < pops => {
+                    self.report(index, VerifyErrorKind::StackUnderflow { depth, needed: pops });
+                    None
+                }
+                (Some(depth), Some((pops, pushes))) => Some(depth - pops + pushes),
+                _ => None,
+            };
+
+            for next in self.successors(index) {
+                worklist.push((next, after));
+            }
+        }
+    }
+}
+
+/// Statically check program against its data segments and register count
+pub fn verify(program: &[Inst], data: &[Vec<Word>], registers: usize) -> Result<(), Vec<VerifyError>> {
+    let mut verifier = Verifier { program, data, registers, errors: Vec::new() };
+
+    for index in 0..program.len() {
+        verifier.check_operands(index);
+    }
+    verifier.check_stack();
+
+    if verifier.errors.is_empty() {
+        return Ok(());
+    }
+
+    verifier.errors.sort_by_key(|err| err.index);
+    Err(verifier.errors)
+}
+
+impl Machine {
+    /// Verify the loaded program, reporting every problem found
+    pub fn verify(&self) -> Result<(), Vec<VerifyError>> {
+        verify(&self.program, &self.data, self.registers.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use InstType::*;
+
+    fn inst(inst_type: InstType, operand: Word) -> Inst {
+        Inst::new(inst_type, [operand, Word::None])
+    }
+
+    fn errors(program: &[Inst]) -> Vec<VerifyError> {
+        let data = vec![vec![Word::Char('a'), Word::Char('\0')]];
+        verify(program, &data, 4).unwrap_err()
+    }
+
+    fn error(index: usize, kind: VerifyErrorKind) -> Vec<VerifyError> {
+        vec![VerifyError { index, kind }]
+    }
+
+    #[test]
+    fn valid_program_passes() {
+        let program = [inst(Pushi, Word::Int(1)), inst(Jeq, Word::Int(0)), inst(Halt, Word::None)];
+        assert_eq!(verify(&program, &[], 4), Ok(()));
+    }
+
+    #[test]
+    fn operand_of_the_wrong_variant() {
+        let program = [inst(Pushi, Word::Float(1.0)), inst(Halt, Word::None)];
+        assert_eq!(errors(&program), error(0, VerifyErrorKind::IllegalOperand(0)));
+    }
+
+    #[test]
+    fn jump_out_of_the_program() {
+        let program = [inst(Jmp, Word::Int(2)), inst(Halt, Word::None)];
+        assert_eq!(errors(&program), error(0, VerifyErrorKind::IllegalTarget(2)));
+    }
+
+    #[test]
+    fn register_out_of_range() {
+        let program = [inst(Pushr, Word::Ptr(Pointer::Register(4))), inst(Halt, Word::None)];
+        assert_eq!(errors(&program), error(0, VerifyErrorKind::IllegalRegister(4)));
+    }
+
+    #[test]
+    fn data_index_out_of_range() {
+        let program = [inst(Pushs, Word::Ptr(Pointer::Data(1))), inst(Halt, Word::None)];
+        assert_eq!(errors(&program), error(0, VerifyErrorKind::IllegalData(1)));
+    }
+
+    #[test]
+    fn pop_of_an_empty_stack() {
+        let program = [inst(Pushi, Word::Int(1)), inst(Plus, Word::None), inst(Halt, Word::None)];
+        assert_eq!(errors(&program), error(1, VerifyErrorKind::StackUnderflow { depth: 1, needed: 2 }));
+    }
+
+    #[test]
+    fn paths_merge_with_different_depths() {
+        let program = [
+            inst(Pushi, Word::Int(0)),
+            inst(Jeq, Word::Int(3)),
+            inst(Pushi, Word::Int(1)),
+            inst(Halt, Word::None),
+        ];
+        assert_eq!(errors(&program), error(3, VerifyErrorKind::StackMismatch { expected: 0, found: 1 }));
+    }
+}
@@ -0,0 +1,228 @@
+use super::*;
+use std::mem::discriminant;
+
+/// Accesses that trigger a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn reads(self) -> bool {
+        matches!(self, Access::Read | Access::ReadWrite)
+    }
+
+    fn writes(self) -> bool {
+        matches!(self, Access::Write | Access::ReadWrite)
+    }
+}
+
+/// What a triggered watchpoint does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    /// Stop run() with Outcome::Watchpoint once the instruction executed
+    Pause,
+    /// Only add the hit to the watch log
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    /// Register, heap address or stack slot
+    pub ptr: Pointer,
+    pub access: Access,
+    pub action: WatchAction,
+}
+
+/// Access of an instruction to a watched word. For reads old and new are
+/// both the value read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub ip: usize,
+    pub ptr: Pointer,
+    /// Read or Write
+    pub access: Access,
+    /// None for stack slots above the top and heap addresses past the end
+    pub old: Option<Word>,
+    pub new: Option<Word>,
+}
+
+/// Watched values before an instruction
+pub struct WatchStart {
+    old: Vec<Option<Word>>,
+}
+
+/// Whether a logged read of n words at start includes ptr
+fn covers((start, n): (Pointer, usize), ptr: Pointer) -> bool {
+    discriminant(&start) == discriminant(&ptr)
+        && start.as_usize() <= ptr.as_usize()
+        && ptr.as_usize() - start.as_usize() < n
+}
+
+impl Machine {
+    /// Watch a register, heap address or stack slot, replacing any
+    /// watchpoint already on it
+    pub fn watch(&mut self, ptr: Pointer, access: Access, action: WatchAction) -> Result<(), Error> {
+        match ptr {
+            Pointer::Register(index) if index >= self.registers.len() => return Err(Error::InvalidPointer),
+            Pointer::Register(_) | Pointer::Heap(_) | Pointer::Stack(_) => {}
+            _ => return Err(Error::InvalidPointer),
+        }
+
+        self.unwatch(ptr);
+        self.watchpoints.push(Watchpoint { ptr, access, action });
+        Ok(())
+    }
+
+    /// Remove the watchpoint on ptr, false if there was none
+    pub fn unwatch(&mut self, ptr: Pointer) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.ptr != ptr);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Hits of all watchpoints so far, pausing ones included
+    pub fn watch_log(&self) -> &[WatchHit] {
+        &self.watch_log
+    }
+
+    pub fn take_watch_log(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_log)
+    }
+
+    /// Current value of a register, heap address or live stack slot
+    pub fn watched_value(&self, ptr: Pointer) -> Option<Word> {
+        match ptr {
+            Pointer::Register(index) => self.registers.get(index).copied(),
+            Pointer::Heap(index) => self.heap.get(index).copied(),
+            Pointer::Stack(index) if index < self.stack.sp => self.stack.stack.get(index).copied(),
+            _ => None,
+        }
+    }
+
+    /// Remember the watched values and start logging reads and writes,
+    /// None if nothing is watched
+    pub fn watch_start(&mut self) -> Option<WatchStart> {
+        self.watch_pause = None;
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+
+        self.journal.get_or_insert_with(Vec::new).clear();
+        *self.reads.borrow_mut() = Some(Vec::new());
+        self.stack.reads = Some(Vec::new());
+
+        Some(WatchStart {
+            old: self.watchpoints.iter().map(|watchpoint| self.watched_value(watchpoint.ptr)).collect(),
+        })
+    }
+
+    /// Log the watched words the instruction at ip read or wrote, writes
+    /// is the journal taken while it executed
+    pub fn watch_end(&mut self, start: WatchStart, ip: usize, writes: &[(Pointer, Option<Word>)]) {
+        let reads = self.reads.borrow_mut().take().unwrap_or_default();
+        let stack_reads = self.stack.reads.take().unwrap_or_default();
+
+        let mut hits = Vec::new();
+        for (watchpoint, old) in self.watchpoints.iter().zip(start.old) {
+            let ptr = watchpoint.ptr;
+            let new = self.watched_value(ptr);
+
+            let read = reads.iter().any(|read| covers(*read, ptr))
+                || matches!(ptr, Pointer::Stack(index) if stack_reads.contains(&index));
+            if read && watchpoint.access.reads() {
+                hits.push((watchpoint.action, WatchHit { ip, ptr, access: Access::Read, old, new: old }));
+            }
+
+            let written = old != new || writes.iter().any(|(write, _)| *write == ptr);
+            if written && watchpoint.access.writes() {
+                hits.push((watchpoint.action, WatchHit { ip, ptr, access: Access::Write, old, new }));
+            }
+        }
+
+        for (action, hit) in hits {
+            if action == WatchAction::Pause && self.watch_pause.is_none() {
+                self.watch_pause = Some(hit);
+            }
+            self.watch_log.push(hit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::run::Outcome;
+
+    fn machine(source: &str) -> Machine {
+        let mut machine = Machine::from_module(assemble(source, "test.asm").unwrap());
+        machine.skip_verify();
+        machine
+    }
+
+    const STORE: &str = "  alloc 2\n  pushi 0\n  pushi 9\n  storeidx\n  exit 0\n";
+
+    #[test]
+    fn write_pauses_after_the_instruction() {
+        let mut machine = machine(STORE);
+        machine.watch(Pointer::Heap(1), Access::Write, WatchAction::Pause).unwrap();
+
+        let alloc = WatchHit { ip: 0, ptr: Pointer::Heap(1), access: Access::Write, old: None, new: Some(Word::Int(0)) };
+        assert_eq!(machine.run(), Ok(Outcome::Watchpoint(alloc)));
+        assert_eq!(machine.ip(), 1);
+
+        let store = WatchHit { ip: 3, old: Some(Word::Int(0)), new: Some(Word::Int(9)), ..alloc };
+        assert_eq!(machine.resume(), Ok(Outcome::Watchpoint(store)));
+        assert_eq!(machine.resume(), Ok(Outcome::Exited(Word::Int(0))));
+        assert_eq!(machine.watch_log(), &[alloc, store]);
+    }
+
+    #[test]
+    fn reads_and_writes_are_logged_without_pausing() {
+        let source = "  alloc 1\n  pushi 0\n  loadidx\n  popr r1\n  exit 0\n";
+        let mut machine = machine(source);
+        machine.watch(Pointer::Heap(1), Access::Read, WatchAction::Log).unwrap();
+        machine.watch(Pointer::Register(1), Access::ReadWrite, WatchAction::Log).unwrap();
+
+        assert_eq!(machine.run(), Ok(Outcome::Exited(Word::Int(0))));
+        assert_eq!(machine.take_watch_log(), vec![
+            WatchHit { ip: 2, ptr: Pointer::Heap(1), access: Access::Read, old: Some(Word::Int(0)), new: Some(Word::Int(0)) },
+            WatchHit { ip: 3, ptr: Pointer::Register(1), access: Access::Write, old: Some(Word::Free), new: Some(Word::Int(0)) },
+        ]);
+        assert!(machine.watch_log().is_empty());
+    }
+
+    #[test]
+    fn read_watchpoint_ignores_writes() {
+        let mut machine = machine(STORE);
+        machine.watch(Pointer::Heap(1), Access::Read, WatchAction::Pause).unwrap();
+        assert_eq!(machine.run(), Ok(Outcome::Exited(Word::Int(0))));
+        assert!(machine.watch_log().is_empty());
+    }
+
+    #[test]
+    fn unwatch_removes_the_watchpoint() {
+        let mut machine = machine(STORE);
+        machine.watch(Pointer::Heap(1), Access::Write, WatchAction::Pause).unwrap();
+        assert!(machine.unwatch(Pointer::Heap(1)));
+        assert!(!machine.unwatch(Pointer::Heap(1)));
+        assert!(machine.watchpoints().is_empty());
+
+        assert_eq!(machine.run(), Ok(Outcome::Exited(Word::Int(0))));
+        assert!(machine.watch_log().is_empty());
+    }
+
+    #[test]
+    fn only_registers_heap_and_stack_are_watched() {
+        let mut machine = machine(STORE);
+        assert_eq!(machine.watch(Pointer::Register(8), Access::Write, WatchAction::Log), Err(Error::InvalidPointer));
+        assert_eq!(machine.watch(Pointer::Data(0), Access::Write, WatchAction::Log), Err(Error::InvalidPointer));
+    }
+}